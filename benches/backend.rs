@@ -11,7 +11,7 @@ use criterion::Criterion;
 use rand::Rng;
 use std::io;
 use std::io::Write;
-use bsdiff_rs::{BsDiff, Backend};
+use bsdiff_rs::{BsDiff, OldIndex};
 use bsdiff_rs::c::CBackend;
 use bsdiff_rs::rust::RustBackend;
 
@@ -62,6 +62,18 @@ pub fn criterion_benchmark(c: &mut Criterion) {
         b.iter(|| BsDiff::<RustBackend>::bspatch_raw(&test_data_1[..], testout, &mut &patch_1[..]))
     });
     group.finish();
+
+    // Unrelated files over a small alphabet: short matches at almost every position
+    let old: Vec<u8> = (0..200_000).map(|_| rng.gen_range(b'a', b'e')).collect();
+    let new: Vec<u8> = (0..200_000).map(|_| rng.gen_range(b'a', b'e')).collect();
+    let index = OldIndex::new(&old);
+
+    let mut group = c.benchmark_group("Search");
+    group.sample_size(10);
+    group.bench_function(BenchmarkId::new("Low Similarity", ""), |b| {
+        b.iter(|| BsDiff::<RustBackend>::bsdiff_raw(&index, &new, &mut write_dummy))
+    });
+    group.finish();
 }
 
 criterion_group!(benches, criterion_benchmark);
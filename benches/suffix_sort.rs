@@ -0,0 +1,43 @@
+#![cfg_attr(tarpaulin, skip)]
+
+#[macro_use]
+extern crate criterion;
+extern crate bsdiff_rs;
+extern crate rand_pcg;
+
+use criterion::{BenchmarkId, Criterion};
+
+use bsdiff_rs::rust::bench::{qsufsort, sais};
+use rand::Rng;
+
+pub fn criterion_benchmark(c: &mut Criterion) {
+    let mut rng = rand_pcg::Pcg64Mcg::new(5);
+    let random: Vec<u8> = (&mut rng)
+        .sample_iter(rand::distributions::Standard)
+        .take(1_000_000)
+        .collect();
+    let record: Vec<u8> = (&mut rng)
+        .sample_iter(rand::distributions::Standard)
+        .take(4096)
+        .collect();
+    let repetitive: Vec<u8> = record.iter().cycle().take(1_000_000).cloned().collect();
+
+    let mut group = c.benchmark_group("SuffixSort");
+    group.sample_size(10);
+    for (name, data) in &[("Random", &random), ("Repetitive", &repetitive)] {
+        group.bench_with_input(BenchmarkId::new("qsufsort", name), data, |b, data| {
+            b.iter(|| qsufsort(data))
+        });
+        group.bench_with_input(BenchmarkId::new("SA-IS", name), data, |b, data| {
+            b.iter(|| sais(data))
+        });
+        #[cfg(feature = "parallel")]
+        group.bench_with_input(BenchmarkId::new("Parallel", name), data, |b, data| {
+            b.iter(|| bsdiff_rs::rust::bench::par_sufsort(data))
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, criterion_benchmark);
+criterion_main!(benches);
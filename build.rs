@@ -1,5 +1,8 @@
 extern crate cc;
 
+// Newer cc versions only build static libraries and deprecate `static_flag`, older ones that
+// Cargo.toml still allows pass `-static` to the compiler
+#[allow(deprecated)]
 fn main() {
     cc::Build::new()
         .file("bsdiff-43/bsdiff.c")
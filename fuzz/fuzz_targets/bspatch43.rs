@@ -0,0 +1,13 @@
+#![no_main]
+use bsdiff_rs::rust::RustBackend;
+use bsdiff_rs::BsDiff;
+use common::{options, OLD};
+use libfuzzer_sys::fuzz_target;
+
+mod common;
+
+fuzz_target!(|data: &[u8]| {
+    for options in &options() {
+        let _ = BsDiff::<RustBackend>::bspatch43_with(OLD, std::io::sink(), data, options);
+    }
+});
@@ -0,0 +1,19 @@
+#![no_main]
+use bsdiff_rs::rust::{bspatch_raw_32bit_with, bspatch_raw_with};
+use common::{options, OLD};
+use libfuzzer_sys::fuzz_target;
+
+mod common;
+
+fuzz_target!(|data: &[u8]| {
+    if data.len() < 2 {
+        return;
+    }
+    let new_len = u16::from_le_bytes([data[0], data[1]]) as usize;
+    let patch = &data[2..];
+    let mut new = vec![0u8; new_len];
+    for options in &options() {
+        let _ = bspatch_raw_with(OLD, &mut new, patch, options);
+        let _ = bspatch_raw_32bit_with(OLD, &mut new, patch, options);
+    }
+});
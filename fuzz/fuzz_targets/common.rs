@@ -0,0 +1,23 @@
+//! What every fuzz target shares: the old data patches apply to, and the options they are
+//! applied with.
+use bsdiff_rs::{PatchLimits, PatchOptions};
+
+pub const OLD: &[u8] = b"The quick brown fox jumps over the lazy dog";
+
+pub fn options() -> [PatchOptions; 2] {
+    let limits = PatchLimits {
+        max_new_size: 1 << 20,
+        max_op_len: 1 << 20,
+        max_memory: 1 << 24,
+    };
+    [
+        PatchOptions {
+            strict: false,
+            limits: limits.clone(),
+        },
+        PatchOptions {
+            strict: true,
+            limits,
+        },
+    ]
+}
@@ -0,0 +1,13 @@
+#![no_main]
+use bsdiff_rs::{jbspatch40_32bit_with, jbspatch40_with};
+use common::{options, OLD};
+use libfuzzer_sys::fuzz_target;
+
+mod common;
+
+fuzz_target!(|data: &[u8]| {
+    for options in &options() {
+        let _ = jbspatch40_with(OLD, std::io::sink(), data, options);
+        let _ = jbspatch40_32bit_with(OLD, std::io::sink(), data, options);
+    }
+});
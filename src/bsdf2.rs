@@ -0,0 +1,93 @@
+use crate::bsdiff40::{diff_sign_magnitude, patch_sign_magnitude};
+use crate::{BsDiffError, BsDiffResult, Codec, DiffOptions, OldData, PatchOptions};
+use std::io::{Read, Write};
+
+const MAGIC_NUMBER_BSDF2: &str = "BSDF2";
+
+///
+/// The codec of each stream of a `BSDF2` patch. Android's bspatch only reads `Codec::None`,
+/// `Codec::Bzip2` and `Codec::Brotli`, and brotli needs the `brotli` feature. The default
+/// compresses every stream with bzip2, like Android's bsdiff.
+///
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Bsdf2Codecs {
+    pub ctrl: Codec,
+    pub diff: Codec,
+    pub extra: Codec,
+}
+
+/// The compressor type Android records in the header for `codec`
+fn bsdf2_id(codec: Codec) -> BsDiffResult<u8> {
+    match codec {
+        Codec::None => Ok(0),
+        Codec::Bzip2 => Ok(1),
+        Codec::Brotli(_) => Ok(2),
+        _ => Err(BsDiffError::InvalidOptions(
+            "BSDF2 streams are uncompressed, bzip2 or brotli",
+        )),
+    }
+}
+
+fn bsdf2_codec(id: u8) -> BsDiffResult<Codec> {
+    match id {
+        0 => Ok(Codec::None),
+        1 => Ok(Codec::Bzip2),
+        2 => Ok(Codec::Brotli(11)),
+        _ => Err(BsDiffError::BadMagic),
+    }
+}
+
+/// Diffs into a `BSDF2` patch, the format of Android's bsdiff, compressing each stream with
+/// its codec from `codecs`.
+pub fn bsdf2_diff<O: OldData + ?Sized, W: Write>(
+    old: &O,
+    new: &[u8],
+    patch: W,
+    codecs: &Bsdf2Codecs,
+) -> BsDiffResult<()> {
+    bsdf2_diff_with(old, new, patch, codecs, &DiffOptions::default())
+}
+
+/// Same as `bsdf2_diff`, with bzip2 streams at the level of `options.compression`.
+/// `options.codec` is ignored in favour of `codecs`.
+pub fn bsdf2_diff_with<O: OldData + ?Sized, W: Write>(
+    old: &O,
+    new: &[u8],
+    patch: W,
+    codecs: &Bsdf2Codecs,
+    options: &DiffOptions,
+) -> BsDiffResult<()> {
+    let mut magic = [0u8; 8];
+    magic[..5].copy_from_slice(MAGIC_NUMBER_BSDF2.as_bytes());
+    magic[5] = bsdf2_id(codecs.ctrl)?;
+    magic[6] = bsdf2_id(codecs.diff)?;
+    magic[7] = bsdf2_id(codecs.extra)?;
+    let codecs = [codecs.ctrl, codecs.diff, codecs.extra];
+    diff_sign_magnitude(old, new, patch, &magic, codecs, options)
+}
+
+/// Applies a `BSDF2` patch made by `bsdf2_diff` or Android's bsdiff.
+pub fn bsdf2_patch<W: Write, R: Read>(old: &[u8], new: W, patch: R) -> BsDiffResult<()> {
+    bsdf2_patch_with(old, new, patch, &PatchOptions::default())
+}
+
+pub fn bsdf2_patch_with<W: Write, R: Read>(
+    old: &[u8],
+    new: W,
+    mut patch: R,
+    options: &PatchOptions,
+) -> BsDiffResult<()> {
+    let mut header = [0u8; 32];
+    patch
+        .read_exact(&mut header)
+        .map_err(BsDiffError::from_header)?;
+    if &header[..5] != MAGIC_NUMBER_BSDF2.as_bytes() {
+        return Err(BsDiffError::BadMagic);
+    }
+    let codecs = [
+        bsdf2_codec(header[5])?,
+        bsdf2_codec(header[6])?,
+        bsdf2_codec(header[7])?,
+    ];
+    patch_sign_magnitude(old, new, patch, &header, codecs, options)
+}
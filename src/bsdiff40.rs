@@ -0,0 +1,200 @@
+use crate::codec::Encoder;
+use crate::rust::{bsdiff_internal, bspatch_internal, BsDiffRequest, BsPatchRequest};
+use crate::{declared_len, read_sections, JBsDiffStreams, MAGIC_NUMBER_BSDIFF_40};
+use crate::{BsDiffError, BsDiffResult, Codec, DiffOptions, OldData, PatchOptions};
+use byteorder::{ByteOrder, LittleEndian};
+use std::convert::TryFrom;
+use std::io::{Read, Write};
+
+/// Writes `value` as the magnitude in the low 63 bits and the sign in the top bit, like `offtout`
+/// in the original bsdiff
+pub(crate) fn offtout(value: i64, buffer: &mut [u8]) {
+    let mut encoded = value.unsigned_abs();
+    if value < 0 {
+        encoded |= 1 << 63;
+    }
+    LittleEndian::write_u64(buffer, encoded);
+}
+
+/// Reads a value written by `offtout`
+pub(crate) fn offtin(buffer: &[u8]) -> i64 {
+    let encoded = LittleEndian::read_u64(buffer);
+    let magnitude = (encoded & !(1 << 63)) as i64;
+    if encoded >> 63 == 1 {
+        -magnitude
+    } else {
+        magnitude
+    }
+}
+
+///
+/// Diffs into the three stream layout of Colin Percival's bsdiff: the 8 byte `magic`, the
+/// lengths of the compressed ctrl and diff streams and of `new`, then the three streams, each
+/// compressed with its codec from `codecs`. Every integer is written by `offtout`.
+///
+pub(crate) fn diff_sign_magnitude<O: OldData + ?Sized, W: Write>(
+    old: &O,
+    new: &[u8],
+    mut patch: W,
+    magic: &[u8; 8],
+    codecs: [Codec; 3],
+    options: &DiffOptions,
+) -> BsDiffResult<()> {
+    for &codec in &codecs {
+        DiffOptions {
+            codec,
+            ..options.clone()
+        }
+        .validate()?;
+    }
+
+    let mut ctrl_data = Vec::new();
+    let mut diff_data = Vec::new();
+    let mut extra_data = Vec::new();
+    {
+        let streams = JBsDiffStreams {
+            ctrl_stream: codecs[0].encoder(&mut ctrl_data, options.compression)?,
+            diff_stream: codecs[1].encoder(&mut diff_data, options.compression)?,
+            extra_stream: codecs[2].encoder(&mut extra_data, options.compression)?,
+        };
+
+        let req = BsDiffRequest {
+            data: streams,
+            ctrl_stream: |data: &mut JBsDiffStreams<Encoder<&mut Vec<u8>>>, buffer| {
+                let mut encoded = [0u8; 3 * 8];
+                for (value, out) in buffer.chunks_exact(8).zip(encoded.chunks_exact_mut(8)) {
+                    offtout(LittleEndian::read_i64(value), out);
+                }
+                Ok(data.ctrl_stream.write_all(&encoded[..buffer.len()])?)
+            },
+            diff_stream: |data, buffer| Ok(data.diff_stream.write_all(buffer)?),
+            extra_stream: |data, buffer| Ok(data.extra_stream.write_all(buffer)?),
+        };
+
+        let streams = bsdiff_internal(old, new, req, true, options)?;
+        streams.ctrl_stream.finish()?;
+        streams.diff_stream.finish()?;
+        streams.extra_stream.finish()?;
+    }
+
+    let mut header = [0u8; 32];
+    header[..8].copy_from_slice(magic);
+    offtout(ctrl_data.len() as i64, &mut header[8..16]);
+    offtout(diff_data.len() as i64, &mut header[16..24]);
+    offtout(new.len() as i64, &mut header[24..32]);
+    patch.write_all(&header)?;
+
+    patch.write_all(&ctrl_data)?;
+    patch.write_all(&diff_data)?;
+    patch.write_all(&extra_data)?;
+
+    Ok(())
+}
+
+/// Applies a patch written by `diff_sign_magnitude`, whose `header` was read and checked already
+pub(crate) fn patch_sign_magnitude<W: Write, R: Read>(
+    old: &[u8],
+    new: W,
+    patch: R,
+    header: &[u8; 32],
+    codecs: [Codec; 3],
+    options: &PatchOptions,
+) -> BsDiffResult<()> {
+    let length = |at: usize| {
+        u64::try_from(offtin(&header[at..at + 8])).map_err(|_| BsDiffError::InvalidControl)
+    };
+    let (ctrl_len, diff_len, out_len) = (length(8)?, length(16)?, length(24)?);
+
+    options.limits.check_new_size(out_len)?;
+    let sections = read_sections(patch, ctrl_len, diff_len, options)?;
+    let out_len = declared_len(out_len)?;
+    let held = sections.len();
+
+    let streams = JBsDiffStreams {
+        ctrl_stream: codecs[0].decoder(&*sections.ctrl_stream)?,
+        diff_stream: codecs[1].decoder(&*sections.diff_stream)?,
+        extra_stream: codecs[2].decoder(&*sections.extra_stream)?,
+    };
+
+    let req = BsPatchRequest {
+        data: streams,
+        ctrl_stream: |data: &mut JBsDiffStreams<Box<dyn Read + '_>>, buffer| {
+            data.ctrl_stream
+                .read_exact(buffer)
+                .map_err(BsDiffError::from_compressed)?;
+            for value in buffer.chunks_exact_mut(8) {
+                let decoded = offtin(value);
+                LittleEndian::write_i64(value, decoded);
+            }
+            Ok(())
+        },
+        diff_stream: |data, buffer| {
+            data.diff_stream
+                .read_exact(buffer)
+                .map_err(BsDiffError::from_compressed)
+        },
+        extra_stream: |data, buffer| {
+            data.extra_stream
+                .read_exact(buffer)
+                .map_err(BsDiffError::from_compressed)
+        },
+    };
+
+    bspatch_internal(old, new, out_len, req, true, held, options)?;
+    Ok(())
+}
+
+const BSDIFF_40_CODECS: [Codec; 3] = [Codec::Bzip2; 3];
+
+///
+/// Diffs into a `BSDIFF40` patch as written by Colin Percival's bsdiff, which stock bspatch
+/// applies. Unlike `jbsdiff40`, negative integers are stored as a magnitude with
+/// the sign in the top bit.
+///
+pub fn bsdiff40<O: OldData + ?Sized, W: Write>(old: &O, new: &[u8], patch: W) -> BsDiffResult<()> {
+    bsdiff40_with(old, new, patch, &DiffOptions::default())
+}
+
+/// Same as `bsdiff40`, with `options.codec` ignored as the format is always bzip2
+pub fn bsdiff40_with<O: OldData + ?Sized, W: Write>(
+    old: &O,
+    new: &[u8],
+    patch: W,
+    options: &DiffOptions,
+) -> BsDiffResult<()> {
+    let magic = <&[u8; 8]>::try_from(MAGIC_NUMBER_BSDIFF_40.as_bytes()).unwrap();
+    diff_sign_magnitude(old, new, patch, magic, BSDIFF_40_CODECS, options)
+}
+
+pub fn bsdiff40_vec<O: OldData + ?Sized>(old: &O, new: &[u8]) -> BsDiffResult<Vec<u8>> {
+    let mut patch = Vec::new();
+    bsdiff40(old, new, &mut patch)?;
+    Ok(patch)
+}
+
+/// Applies a `BSDIFF40` patch made by `bsdiff40` or Colin Percival's bsdiff
+pub fn bspatch40<W: Write, R: Read>(old: &[u8], new: W, patch: R) -> BsDiffResult<()> {
+    bspatch40_with(old, new, patch, &PatchOptions::default())
+}
+
+pub fn bspatch40_with<W: Write, R: Read>(
+    old: &[u8],
+    new: W,
+    mut patch: R,
+    options: &PatchOptions,
+) -> BsDiffResult<()> {
+    let mut header = [0u8; 32];
+    patch
+        .read_exact(&mut header)
+        .map_err(BsDiffError::from_header)?;
+    if &header[..8] != MAGIC_NUMBER_BSDIFF_40.as_bytes() {
+        return Err(BsDiffError::BadMagic);
+    }
+    patch_sign_magnitude(old, new, patch, &header, BSDIFF_40_CODECS, options)
+}
+
+pub fn bspatch40_vec<R: Read>(old: &[u8], patch: R) -> BsDiffResult<Vec<u8>> {
+    let mut new = Vec::new();
+    bspatch40(old, &mut new, patch)?;
+    Ok(new)
+}
@@ -1,6 +1,6 @@
 use super::c_bindings;
-use crate::BsDiffResult;
-use std::io::{Error, ErrorKind, Write};
+use crate::{BsDiffError, BsDiffResult};
+use std::io::Write;
 use std::os::raw::c_void;
 
 ///
@@ -31,10 +31,7 @@ pub fn bsdiff_raw<W: Write>(old: &[u8], new: &[u8], mut patch: W) -> BsDiffResul
     if exit_code == 0 {
         Ok(())
     } else {
-        Err(Error::new(
-            ErrorKind::Other,
-            format!("C code returned {}", exit_code),
-        ))
+        Err(BsDiffError::Backend(exit_code))
     }
 }
 
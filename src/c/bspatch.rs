@@ -1,6 +1,6 @@
 use super::c_bindings;
-use crate::BsDiffResult;
-use std::io::{Error, ErrorKind, Read};
+use crate::{BsDiffError, BsDiffResult};
+use std::io::Read;
 use std::os::raw::c_void;
 
 ///
@@ -30,10 +30,7 @@ pub fn bspatch_raw<R: Read>(old: &[u8], new: &mut [u8], mut patch: R) -> BsDiffR
     if exit_code == 0 {
         Ok(())
     } else {
-        Err(Error::new(
-            ErrorKind::Other,
-            format!("C code returned {}", exit_code),
-        ))
+        Err(BsDiffError::Backend(exit_code))
     }
 }
 
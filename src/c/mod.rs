@@ -1,8 +1,34 @@
+use crate::{Backend, BsDiffResult, PatchOptions};
+use std::io::{Read, Write};
+
 mod bsdiff;
 pub use bsdiff::bsdiff_raw;
 mod bspatch;
 pub use bspatch::bspatch_raw;
 
+///
+/// Wraps the C implementation of bsdiff from mendsley/bsdiff.
+///
+pub struct CBackend;
+
+impl Backend for CBackend {
+    #[inline]
+    fn bsdiff_raw<W: Write>(old: &[u8], new: &[u8], patch: W) -> BsDiffResult<()> {
+        bsdiff_raw(old, new, patch)
+    }
+
+    /// The C code does its own validation, so `options.strict` has no effect.
+    #[inline]
+    fn bspatch_raw_with<R: Read>(
+        old: &[u8],
+        new: &mut [u8],
+        patch: R,
+        _options: &PatchOptions,
+    ) -> BsDiffResult<()> {
+        bspatch_raw(old, new, patch)
+    }
+}
+
 mod c_bindings {
     use std::os::raw::c_void;
 
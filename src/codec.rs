@@ -0,0 +1,266 @@
+use crate::{BsDiffError, BsDiffResult, Compression};
+use bzip2::read::BzDecoder;
+use bzip2::write::BzEncoder;
+use std::io::{self, Read, Write};
+
+///
+/// How the control, diff and extra data of a `bsdiff43` patch are compressed.
+///
+/// `Bzip2` writes the original Endsley format, which every bspatch applies. Every other codec
+/// writes a variant header recording the codec, which `bspatch43` detects. `Gzip`, `Xz`, `Zstd`
+/// and `Brotli` need the cargo feature of the same name, else diffing or patching with them
+/// fails with `BsDiffError::UnsupportedCodec`.
+///
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Codec {
+    /// bzip2 at the level of `DiffOptions::compression`
+    #[default]
+    Bzip2,
+    /// No compression at all, for patches compressed again as a whole later
+    None,
+    /// gzip at a level from 0 to 9
+    Gzip(u32),
+    /// xz at a preset from 0 to 9
+    Xz(u32),
+    /// zstd at a level from 1 to 22
+    Zstd(i32),
+    /// brotli at a quality from 0 to 11
+    Brotli(u32),
+}
+
+impl Codec {
+    /// The byte recording the codec in a variant header
+    pub(crate) fn id(self) -> u8 {
+        match self {
+            Codec::None => 0,
+            Codec::Bzip2 => 1,
+            Codec::Gzip(_) => 2,
+            Codec::Xz(_) => 3,
+            Codec::Zstd(_) => 4,
+            Codec::Brotli(_) => 5,
+        }
+    }
+
+    /// The codec recorded as `id`, at its default level. Decoding does not need the level.
+    pub(crate) fn from_id(id: u8) -> Option<Codec> {
+        match id {
+            0 => Some(Codec::None),
+            1 => Some(Codec::Bzip2),
+            2 => Some(Codec::Gzip(6)),
+            3 => Some(Codec::Xz(6)),
+            4 => Some(Codec::Zstd(3)),
+            5 => Some(Codec::Brotli(11)),
+            _ => None,
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            Codec::None => "none",
+            Codec::Bzip2 => "bzip2",
+            Codec::Gzip(_) => "gzip",
+            Codec::Xz(_) => "xz",
+            Codec::Zstd(_) => "zstd",
+            Codec::Brotli(_) => "brotli",
+        }
+    }
+
+    /// Whether this build can encode and decode the codec
+    pub fn available(self) -> bool {
+        match self {
+            Codec::None | Codec::Bzip2 => true,
+            Codec::Gzip(_) => cfg!(feature = "gzip"),
+            Codec::Xz(_) => cfg!(feature = "xz"),
+            Codec::Zstd(_) => cfg!(feature = "zstd"),
+            Codec::Brotli(_) => cfg!(feature = "brotli"),
+        }
+    }
+
+    fn check_available(self) -> BsDiffResult<()> {
+        if self.available() {
+            Ok(())
+        } else {
+            Err(BsDiffError::UnsupportedCodec(self.name()))
+        }
+    }
+
+    /// Rejects codecs that were not compiled in and levels they do not have.
+    /// The level of `Bzip2` is checked along with the other `DiffOptions`.
+    pub(crate) fn validate(self) -> BsDiffResult<()> {
+        self.check_available()?;
+        let invalid = match self {
+            Codec::None | Codec::Bzip2 => None,
+            Codec::Gzip(level) if level > 9 => Some("gzip levels run from 0 to 9"),
+            Codec::Xz(preset) if preset > 9 => Some("xz presets run from 0 to 9"),
+            Codec::Zstd(level) if !(1..=22).contains(&level) => {
+                Some("zstd levels run from 1 to 22")
+            }
+            Codec::Brotli(quality) if quality > 11 => Some("brotli qualities run from 0 to 11"),
+            _ => None,
+        };
+        match invalid {
+            Some(reason) => Err(BsDiffError::InvalidOptions(reason)),
+            None => Ok(()),
+        }
+    }
+
+    /// Compresses everything written to the returned encoder into `patch`
+    pub(crate) fn encoder<W: Write>(
+        self,
+        patch: W,
+        compression: Compression,
+    ) -> BsDiffResult<Encoder<W>> {
+        self.check_available()?;
+        Ok(match self {
+            Codec::Bzip2 => Encoder::Bzip2(BzEncoder::new(patch, compression.bzip2())),
+            Codec::None => Encoder::None(patch),
+            #[cfg(feature = "gzip")]
+            Codec::Gzip(level) => Encoder::Gzip(flate2::write::GzEncoder::new(
+                patch,
+                flate2::Compression::new(level),
+            )),
+            #[cfg(feature = "xz")]
+            Codec::Xz(preset) => Encoder::Xz(xz2::write::XzEncoder::new(patch, preset)),
+            #[cfg(feature = "zstd")]
+            Codec::Zstd(level) => Encoder::Zstd(zstd::stream::write::Encoder::new(patch, level)?),
+            #[cfg(feature = "brotli")]
+            Codec::Brotli(quality) => Encoder::Brotli(Box::new(brotli::CompressorWriter::new(
+                Trap::new(patch),
+                BROTLI_BUFFER_LEN,
+                quality,
+                BROTLI_WINDOW_BITS,
+            ))),
+            #[allow(unreachable_patterns)]
+            _ => unreachable!("Codec not compiled in"),
+        })
+    }
+
+    /// Decompresses `patch`
+    pub(crate) fn decoder<'a, R: Read + 'a>(self, patch: R) -> BsDiffResult<Box<dyn Read + 'a>> {
+        self.check_available()?;
+        Ok(match self {
+            Codec::Bzip2 => Box::new(BzDecoder::new(patch)),
+            Codec::None => Box::new(patch),
+            #[cfg(feature = "gzip")]
+            Codec::Gzip(_) => Box::new(flate2::read::GzDecoder::new(patch)),
+            #[cfg(feature = "xz")]
+            Codec::Xz(_) => Box::new(xz2::read::XzDecoder::new(patch)),
+            #[cfg(feature = "zstd")]
+            Codec::Zstd(_) => Box::new(zstd::stream::read::Decoder::new(patch)?),
+            #[cfg(feature = "brotli")]
+            Codec::Brotli(_) => Box::new(brotli::Decompressor::new(patch, BROTLI_BUFFER_LEN)),
+            #[allow(unreachable_patterns)]
+            _ => unreachable!("Codec not compiled in"),
+        })
+    }
+}
+
+#[cfg(feature = "brotli")]
+const BROTLI_BUFFER_LEN: usize = 1 << 16;
+
+/// The largest window brotli supports without its large window extension
+#[cfg(feature = "brotli")]
+const BROTLI_WINDOW_BITS: u32 = 24;
+
+/// The writer of a `Codec`, which must be finished to complete the stream
+pub(crate) enum Encoder<W: Write> {
+    Bzip2(BzEncoder<W>),
+    None(W),
+    #[cfg(feature = "gzip")]
+    Gzip(flate2::write::GzEncoder<W>),
+    #[cfg(feature = "xz")]
+    Xz(xz2::write::XzEncoder<W>),
+    #[cfg(feature = "zstd")]
+    Zstd(zstd::stream::write::Encoder<'static, W>),
+    #[cfg(feature = "brotli")]
+    Brotli(Box<brotli::CompressorWriter<Trap<W>>>),
+}
+
+impl<W: Write> Encoder<W> {
+    /// Writes the end of the stream and returns the underlying writer
+    pub(crate) fn finish(self) -> io::Result<W> {
+        match self {
+            Encoder::Bzip2(encoder) => encoder.finish(),
+            Encoder::None(patch) => Ok(patch),
+            #[cfg(feature = "gzip")]
+            Encoder::Gzip(encoder) => encoder.finish(),
+            #[cfg(feature = "xz")]
+            Encoder::Xz(encoder) => encoder.finish(),
+            #[cfg(feature = "zstd")]
+            Encoder::Zstd(encoder) => encoder.finish(),
+            #[cfg(feature = "brotli")]
+            Encoder::Brotli(encoder) => encoder.into_inner().finish(),
+        }
+    }
+
+    fn writer(&mut self) -> &mut dyn Write {
+        match self {
+            Encoder::Bzip2(encoder) => encoder,
+            Encoder::None(patch) => patch,
+            #[cfg(feature = "gzip")]
+            Encoder::Gzip(encoder) => encoder,
+            #[cfg(feature = "xz")]
+            Encoder::Xz(encoder) => encoder,
+            #[cfg(feature = "zstd")]
+            Encoder::Zstd(encoder) => encoder,
+            #[cfg(feature = "brotli")]
+            Encoder::Brotli(encoder) => encoder,
+        }
+    }
+}
+
+impl<W: Write> Write for Encoder<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.writer().write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.writer().flush()
+    }
+}
+
+///
+/// Keeps the first error of the writer it wraps. brotli discards errors while writing the end
+/// of its stream, so they are reported from here instead.
+///
+#[cfg(feature = "brotli")]
+pub(crate) struct Trap<W> {
+    inner: W,
+    error: Option<io::Error>,
+}
+
+#[cfg(feature = "brotli")]
+impl<W: Write> Trap<W> {
+    fn new(inner: W) -> Self {
+        Trap { inner, error: None }
+    }
+
+    fn finish(self) -> io::Result<W> {
+        match self.error {
+            Some(err) => Err(err),
+            None => Ok(self.inner),
+        }
+    }
+
+    fn keep<T>(&mut self, result: io::Result<T>) -> io::Result<T> {
+        if let Err(err) = &result {
+            if self.error.is_none() {
+                self.error = Some(io::Error::new(err.kind(), err.to_string()));
+            }
+        }
+        result
+    }
+}
+
+#[cfg(feature = "brotli")]
+impl<W: Write> Write for Trap<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let result = self.inner.write(buf);
+        self.keep(result)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        let result = self.inner.flush();
+        self.keep(result)
+    }
+}
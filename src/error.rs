@@ -0,0 +1,111 @@
+use std::error::Error;
+use std::fmt;
+use std::io;
+
+pub type BsDiffResult<D> = Result<D, BsDiffError>;
+
+///
+/// Everything that can go wrong while generating or applying a patch.
+///
+#[derive(Debug)]
+pub enum BsDiffError {
+    /// An error from the underlying reader or writer
+    Io(io::Error),
+    /// The patch does not start with the magic number of the expected container format
+    BadMagic,
+    /// The patch ended before its header was complete
+    TruncatedHeader,
+    /// A compressed section of the patch could not be decoded
+    CorruptStream(io::Error),
+    /// A control entry describes an impossible operation
+    InvalidControl,
+    /// The patch describes output of a different length than the header declared
+    OutputLengthMismatch { expected: usize, actual: usize },
+    /// The C backend returned a non-zero exit code
+    Backend(i32),
+    /// The patch asks for more than a configured `PatchLimits` allows
+    LimitExceeded { requested: u64, limit: u64 },
+    /// A saved `OldIndex` is damaged or was written by an incompatible version
+    CorruptIndex,
+    /// A saved `OldIndex` was built from different old data
+    IndexMismatch,
+    /// A `DiffOptions` setting is out of range
+    InvalidOptions(&'static str),
+    /// The named codec was not compiled in, see `Codec`
+    UnsupportedCodec(&'static str),
+}
+
+impl BsDiffError {
+    /// Classifies an error raised while reading from a decompressor.
+    /// Decoding failures and premature ends of the stream mean the patch itself is damaged.
+    pub(crate) fn from_compressed(err: io::Error) -> BsDiffError {
+        match err.kind() {
+            io::ErrorKind::InvalidInput
+            | io::ErrorKind::InvalidData
+            | io::ErrorKind::UnexpectedEof => BsDiffError::CorruptStream(err),
+            _ => BsDiffError::Io(err),
+        }
+    }
+
+    /// Classifies an error raised while reading a fixed size header.
+    pub(crate) fn from_header(err: io::Error) -> BsDiffError {
+        match err.kind() {
+            io::ErrorKind::UnexpectedEof => BsDiffError::TruncatedHeader,
+            _ => BsDiffError::Io(err),
+        }
+    }
+}
+
+impl fmt::Display for BsDiffError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            BsDiffError::Io(err) => write!(f, "I/O error: {}", err),
+            BsDiffError::BadMagic => write!(f, "Patch has an unrecognized magic number"),
+            BsDiffError::TruncatedHeader => write!(f, "Patch header is truncated"),
+            BsDiffError::CorruptStream(err) => write!(f, "Compressed patch data is corrupt: {}", err),
+            BsDiffError::InvalidControl => write!(f, "Patch Instructions Invalid"),
+            BsDiffError::OutputLengthMismatch { expected, actual } => write!(
+                f,
+                "Patch produces {} bytes but the header declares {}",
+                actual, expected
+            ),
+            BsDiffError::Backend(code) => write!(f, "C code returned {}", code),
+            BsDiffError::LimitExceeded { requested, limit } => write!(
+                f,
+                "Patch requires {} bytes but the limit is {}",
+                requested, limit
+            ),
+            BsDiffError::CorruptIndex => write!(f, "Suffix array index is corrupt"),
+            BsDiffError::IndexMismatch => write!(f, "Suffix array index belongs to different old data"),
+            BsDiffError::InvalidOptions(reason) => write!(f, "Invalid diff options: {}", reason),
+            BsDiffError::UnsupportedCodec(codec) => {
+                write!(f, "Built without the {} codec, see its cargo feature", codec)
+            }
+        }
+    }
+}
+
+impl Error for BsDiffError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            BsDiffError::Io(err) | BsDiffError::CorruptStream(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for BsDiffError {
+    fn from(err: io::Error) -> Self {
+        BsDiffError::Io(err)
+    }
+}
+
+impl From<BsDiffError> for io::Error {
+    fn from(err: BsDiffError) -> Self {
+        match err {
+            BsDiffError::Io(err) => err,
+            BsDiffError::Backend(_) => io::Error::other(err),
+            other => io::Error::new(io::ErrorKind::InvalidData, other),
+        }
+    }
+}
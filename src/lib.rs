@@ -11,197 +11,539 @@ extern crate libc;
 use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
 use bzip2::read::BzDecoder;
 use bzip2::write::BzEncoder;
-use bzip2::Compression;
-use std::io::{Read, Write};
+use std::convert::TryFrom;
+use std::io::{self, Read, Write};
+use std::marker::PhantomData;
+
+mod error;
+pub use error::{BsDiffError, BsDiffResult};
+mod codec;
+use codec::Encoder;
+pub use codec::Codec;
+mod options;
+pub use options::{Compression, DiffOptions, PatchLimits, PatchOptions};
+mod bsdiff40;
+pub use bsdiff40::{bsdiff40, bsdiff40_vec, bsdiff40_with, bspatch40, bspatch40_vec, bspatch40_with};
+mod seekable;
+pub use seekable::{jbsdiff40_seekable, jbsdiff40_seekable_in, jbsdiff40_seekable_with};
+pub use seekable::{jbspatch40_seekable, jbspatch40_seekable_with};
+mod bsdf2;
+pub use bsdf2::{bsdf2_diff, bsdf2_diff_with, bsdf2_patch, bsdf2_patch_with, Bsdf2Codecs};
+mod optimize;
+pub use optimize::{bsdiff43_optimize, bsdiff_raw_optimize, jbsdiff40_optimize};
+
+pub mod c;
+pub mod rust;
+
+pub use rust::{OldData, OldIndex, SuffixIndex};
+use rust::{bsdiff_internal, bspatch_internal, BsDiffRequest, BsPatchRequest};
+
+///
+/// An implementation of the raw bsdiff algorithm.
+/// Both `rust::RustBackend` and `c::CBackend` are always compiled, so the backend can be chosen per call through `BsDiff`.
+///
+pub trait Backend {
+    ///
+    /// Access to the raw bsdiff algorithm.
+    /// This function does not add any headers or length information to the patch.
+    ///
+    fn bsdiff_raw<W: Write>(old: &[u8], new: &[u8], patch: W) -> BsDiffResult<()>;
+
+    ///
+    /// Same as `bsdiff_raw`, with control over the matching heuristics.
+    /// The default implementation ignores the options.
+    ///
+    fn bsdiff_raw_with<W: Write>(
+        old: &[u8],
+        new: &[u8],
+        patch: W,
+        _options: &DiffOptions,
+    ) -> BsDiffResult<()> {
+        Self::bsdiff_raw(old, new, patch)
+    }
+
+    ///
+    /// Same as `bsdiff_raw_with`, with the suffix array of the old data already built.
+    /// The default implementation ignores the index and diffs the old data directly.
+    ///
+    fn bsdiff_raw_indexed<W: Write>(
+        old: &OldIndex,
+        new: &[u8],
+        patch: W,
+        options: &DiffOptions,
+    ) -> BsDiffResult<()> {
+        Self::bsdiff_raw_with(old.old(), new, patch, options)
+    }
+
+    ///
+    /// Access to the raw bspatch algorithm.
+    /// This function does not read a header or any length information.
+    /// The output buffer must be the correct size.
+    ///
+    fn bspatch_raw<R: Read>(old: &[u8], new: &mut [u8], patch: R) -> BsDiffResult<()> {
+        Self::bspatch_raw_with(old, new, patch, &PatchOptions::default())
+    }
+
+    /// Same as `bspatch_raw`, with control over how the patch is validated.
+    fn bspatch_raw_with<R: Read>(
+        old: &[u8],
+        new: &mut [u8],
+        patch: R,
+        options: &PatchOptions,
+    ) -> BsDiffResult<()>;
+
+    ///
+    /// Applies a raw patch read from a decompressing stream, writing `new_len` bytes to `new`.
+    /// The default implementation patches into an intermediate buffer.
+    ///
+    fn bspatch_stream<W: Write, R: Read>(
+        old: &[u8],
+        mut new: W,
+        new_len: usize,
+        patch: R,
+        options: &PatchOptions,
+    ) -> BsDiffResult<()> {
+        options.limits.check_memory(new_len as u64)?;
+        // The size comes from the patch, so fail rather than abort when it cannot be allocated
+        let mut new_buffer = Vec::new();
+        new_buffer.try_reserve_exact(new_len).map_err(|_| {
+            BsDiffError::Io(io::Error::new(
+                io::ErrorKind::OutOfMemory,
+                "Declared new size does not fit in memory",
+            ))
+        })?;
+        new_buffer.resize(new_len, 0);
+        Self::bspatch_raw_with(old, &mut new_buffer[..], patch, options)?;
+        new.write_all(&new_buffer[..])?;
+        Ok(())
+    }
+}
 
-#[path = "c/mod.rs"]
 #[cfg(feature = "c_backend")]
-mod backend;
+pub type DefaultBackend = c::CBackend;
 
-#[path = "rust/mod.rs"]
 #[cfg(not(feature = "c_backend"))]
-mod backend;
+pub type DefaultBackend = rust::RustBackend;
+
+///
+/// The bsdiff and BSDIFF43 entry points, using the backend `B`.
+/// The free functions of this crate are shorthands for `BsDiff<DefaultBackend>`.
+///
+pub struct BsDiff<B: Backend>(PhantomData<B>);
+
+impl<B: Backend> BsDiff<B> {
+    #[inline]
+    pub fn bsdiff_raw<O: OldData + ?Sized, W: Write>(
+        old: &O,
+        new: &[u8],
+        patch: W,
+    ) -> BsDiffResult<()> {
+        Self::bsdiff_raw_with(old, new, patch, &DiffOptions::default())
+    }
+
+    #[inline]
+    pub fn bsdiff_raw_with<O: OldData + ?Sized, W: Write>(
+        old: &O,
+        new: &[u8],
+        patch: W,
+        options: &DiffOptions,
+    ) -> BsDiffResult<()> {
+        match old.old_index() {
+            Some(index) => B::bsdiff_raw_indexed(index, new, patch, options),
+            None => B::bsdiff_raw_with(old.old_bytes(), new, patch, options),
+        }
+    }
+
+    #[inline]
+    pub fn bspatch_raw<R: Read>(old: &[u8], new: &mut [u8], patch: R) -> BsDiffResult<()> {
+        B::bspatch_raw(old, new, patch)
+    }
+
+    #[inline]
+    pub fn bspatch_raw_with<R: Read>(
+        old: &[u8],
+        new: &mut [u8],
+        patch: R,
+        options: &PatchOptions,
+    ) -> BsDiffResult<()> {
+        B::bspatch_raw_with(old, new, patch, options)
+    }
+
+    pub fn bsdiff43<O: OldData + ?Sized, W: Write>(
+        old: &O,
+        new: &[u8],
+        patch: W,
+    ) -> BsDiffResult<()> {
+        Self::bsdiff43_with(old, new, patch, &DiffOptions::default())
+    }
+
+    pub fn bsdiff43_with<O: OldData + ?Sized, W: Write>(
+        old: &O,
+        new: &[u8],
+        patch: W,
+        options: &DiffOptions,
+    ) -> BsDiffResult<()> {
+        options.validate()?;
+        let mut compress = bsdiff43_encoder(patch, new.len(), options)?;
+        Self::bsdiff_raw_with(old, new, &mut compress, options)?;
+        compress.finish()?;
+        Ok(())
+    }
+
+    pub fn bsdiff43_vec<O: OldData + ?Sized>(old: &O, new: &[u8]) -> BsDiffResult<Vec<u8>> {
+        let mut patch = Vec::new();
+        Self::bsdiff43(old, new, &mut patch)?;
+        Ok(patch)
+    }
+
+    pub fn bspatch43<W: Write, R: Read>(old: &[u8], new: W, patch: R) -> BsDiffResult<()> {
+        Self::bspatch43_with(old, new, patch, &PatchOptions::default())
+    }
+
+    pub fn bspatch43_with<W: Write, R: Read>(
+        old: &[u8],
+        new: W,
+        mut patch: R,
+        options: &PatchOptions,
+    ) -> BsDiffResult<()> {
+        let mut header = [0u8; 16];
+        patch.read_exact(&mut header).map_err(BsDiffError::from_header)?;
+        let codec = if header == MAGIC_NUMBER_BSDIFF_43.as_bytes() {
+            Codec::Bzip2
+        } else if header == MAGIC_NUMBER_BSDIFF_43_VARIANT.as_bytes() {
+            let id = patch.read_u8().map_err(BsDiffError::from_header)?;
+            Codec::from_id(id).ok_or(BsDiffError::BadMagic)?
+        } else {
+            return Err(BsDiffError::BadMagic);
+        };
+        let new_size = patch
+            .read_u64::<LittleEndian>()
+            .map_err(BsDiffError::from_header)?;
+        options.limits.check_new_size(new_size)?;
+        let new_size = declared_len(new_size)?;
+        B::bspatch_stream(old, new, new_size, codec.decoder(patch)?, options)
+    }
+
+    pub fn bspatch43_vec<R: Read>(old: &[u8], patch: R) -> BsDiffResult<Vec<u8>> {
+        let mut new = Vec::new();
+        Self::bspatch43(old, &mut new, patch)?;
+        Ok(new)
+    }
+}
 
 #[inline]
-pub fn bsdiff_raw<W: Write>(old: &[u8], new: &[u8], patch: W) -> BsDiffResult<()> {
-    backend::bsdiff_raw(old, new, patch)
+pub fn bsdiff_raw<O: OldData + ?Sized, W: Write>(old: &O, new: &[u8], patch: W) -> BsDiffResult<()> {
+    BsDiff::<DefaultBackend>::bsdiff_raw(old, new, patch)
+}
+
+#[inline]
+pub fn bsdiff_raw_with<O: OldData + ?Sized, W: Write>(
+    old: &O,
+    new: &[u8],
+    patch: W,
+    options: &DiffOptions,
+) -> BsDiffResult<()> {
+    BsDiff::<DefaultBackend>::bsdiff_raw_with(old, new, patch, options)
 }
 
 #[inline]
 pub fn bspatch_raw<R: Read>(old: &[u8], new: &mut [u8], patch: R) -> BsDiffResult<()> {
-    backend::bspatch_raw(old, new, patch)
+    BsDiff::<DefaultBackend>::bspatch_raw(old, new, patch)
 }
 
-#[cfg(not(feature = "c_backend"))]
-use backend::{bsdiff_internal, bspatch_internal, BsDiffRequest, BsPatchRequest};
+#[inline]
+pub fn bspatch_raw_with<R: Read>(
+    old: &[u8],
+    new: &mut [u8],
+    patch: R,
+    options: &PatchOptions,
+) -> BsDiffResult<()> {
+    BsDiff::<DefaultBackend>::bspatch_raw_with(old, new, patch, options)
+}
 
-pub type BsDiffResult<D> = std::io::Result<D>;
+pub fn bsdiff43<O: OldData + ?Sized, W: Write>(old: &O, new: &[u8], patch: W) -> BsDiffResult<()> {
+    BsDiff::<DefaultBackend>::bsdiff43(old, new, patch)
+}
 
-pub fn bsdiff43<W: Write>(old: &[u8], new: &[u8], mut patch: W) -> BsDiffResult<()> {
-    patch.write_all(MAGIC_NUMBER_BSDIFF_43.as_bytes()).unwrap();
-    patch.write_u64::<LittleEndian>(new.len() as u64).unwrap();
-    let mut compress = BzEncoder::new(patch, Compression::Best);
-    bsdiff_raw(old, new, &mut compress)?;
-    compress.finish()?;
-    Ok(())
+pub fn bsdiff43_with<O: OldData + ?Sized, W: Write>(
+    old: &O,
+    new: &[u8],
+    patch: W,
+    options: &DiffOptions,
+) -> BsDiffResult<()> {
+    BsDiff::<DefaultBackend>::bsdiff43_with(old, new, patch, options)
 }
 
-pub fn bsdiff43_vec(old: &[u8], new: &[u8]) -> BsDiffResult<Vec<u8>> {
-    let mut patch = Vec::new();
-    bsdiff43(old, new, &mut patch)?;
-    Ok(patch)
+pub fn bsdiff43_vec<O: OldData + ?Sized>(old: &O, new: &[u8]) -> BsDiffResult<Vec<u8>> {
+    BsDiff::<DefaultBackend>::bsdiff43_vec(old, new)
 }
 
 const MAGIC_NUMBER_BSDIFF_43: &str = "ENDSLEY/BSDIFF43";
-
-pub fn bspatch43<W: Write, R: Read>(old: &[u8], new: W, mut patch: R) -> BsDiffResult<()> {
-    let mut header = [0u8; 16];
-    patch.read_exact(&mut header).unwrap();
-    assert_eq!(&header, MAGIC_NUMBER_BSDIFF_43.as_bytes());
-    let new_size = patch.read_u64::<LittleEndian>().unwrap() as usize;
-    let decompress = BzDecoder::new(patch);
-    #[cfg(feature = "c_backend")]
-    {
-        let mut new = new;
-        let mut new_buffer = vec![0u8; new_size];
-        let mut decompress = decompress;
-        bspatch_raw(old, &mut new_buffer[..], &mut decompress)?;
-        new.write_all(&mut new_buffer[..]).unwrap();
+/// Followed by the id of the `Codec`, then the same as `MAGIC_NUMBER_BSDIFF_43`
+const MAGIC_NUMBER_BSDIFF_43_VARIANT: &str = "ENDSLEY/BSDIFC43";
+
+/// Writes the header of a `bsdiff43` patch, returning the encoder its raw patch goes through
+fn bsdiff43_encoder<W: Write>(
+    mut patch: W,
+    new_len: usize,
+    options: &DiffOptions,
+) -> BsDiffResult<Encoder<W>> {
+    if options.codec == Codec::Bzip2 {
+        patch.write_all(MAGIC_NUMBER_BSDIFF_43.as_bytes())?;
+    } else {
+        patch.write_all(MAGIC_NUMBER_BSDIFF_43_VARIANT.as_bytes())?;
+        patch.write_u8(options.codec.id())?;
     }
-    #[cfg(not(feature = "c_backend"))]
-    {
-        // Rust Backend can avoid a copy by using bspatch_internal
-        let write_fun: fn(&mut BzDecoder<_>, &mut [u8]) -> BsDiffResult<()> =
-            |data, buffer| data.read_exact(buffer);
-        let req = BsPatchRequest {
-            data: decompress,
-            ctrl_stream: write_fun,
-            diff_stream: write_fun,
-            extra_stream: write_fun,
-        };
-        backend::bspatch_internal(old, new, new_size, req, true)?;
-    }
-    Ok(())
+    patch.write_u64::<LittleEndian>(new_len as u64)?;
+    options.codec.encoder(patch, options.compression)
+}
+
+pub fn bspatch43<W: Write, R: Read>(old: &[u8], new: W, patch: R) -> BsDiffResult<()> {
+    BsDiff::<DefaultBackend>::bspatch43(old, new, patch)
+}
+
+pub fn bspatch43_with<W: Write, R: Read>(
+    old: &[u8],
+    new: W,
+    patch: R,
+    options: &PatchOptions,
+) -> BsDiffResult<()> {
+    BsDiff::<DefaultBackend>::bspatch43_with(old, new, patch, options)
 }
 
 pub fn bspatch43_vec<R: Read>(old: &[u8], patch: R) -> BsDiffResult<Vec<u8>> {
-    let mut new = Vec::new();
-    bspatch43(old, &mut new, patch)?;
-    Ok(new)
+    BsDiff::<DefaultBackend>::bspatch43_vec(old, patch)
 }
 
-#[cfg(not(feature = "c_backend"))]
 const MAGIC_NUMBER_BSDIFF_40: &str = "BSDIFF40";
 
-#[cfg(not(feature = "c_backend"))]
 struct JBsDiffStreams<S> {
     pub ctrl_stream: S,
     pub diff_stream: S,
     pub extra_stream: S,
 }
 
-#[cfg(not(feature = "c_backend"))]
-pub fn jbsdiff40<W: Write>(old: &[u8], new: &[u8], patch: W) -> BsDiffResult<()> {
-    jbsdiff40_sized(old, new, patch, true)
+impl JBsDiffStreams<Vec<u8>> {
+    /// The memory taken up by the three sections
+    fn len(&self) -> u64 {
+        (self.ctrl_stream.len() + self.diff_stream.len() + self.extra_stream.len()) as u64
+    }
 }
 
-#[cfg(not(feature = "c_backend"))]
-pub fn jbsdiff40_32bit<W: Write>(old: &[u8], new: &[u8], patch: W) -> BsDiffResult<()> {
-    jbsdiff40_sized(old, new, patch, false)
+pub fn jbsdiff40<O: OldData + ?Sized, W: Write>(old: &O, new: &[u8], patch: W) -> BsDiffResult<()> {
+    jbsdiff40_sized(old, new, patch, true, &DiffOptions::default())
 }
 
-#[cfg(not(feature = "c_backend"))]
-fn jbsdiff40_sized<W: Write>(old: &[u8], new: &[u8], mut patch: W, x64_bit: bool) -> BsDiffResult<()> {
+pub fn jbsdiff40_with<O: OldData + ?Sized, W: Write>(
+    old: &O,
+    new: &[u8],
+    patch: W,
+    options: &DiffOptions,
+) -> BsDiffResult<()> {
+    jbsdiff40_sized(old, new, patch, true, options)
+}
+
+pub fn jbsdiff40_32bit<O: OldData + ?Sized, W: Write>(
+    old: &O,
+    new: &[u8],
+    patch: W,
+) -> BsDiffResult<()> {
+    jbsdiff40_sized(old, new, patch, false, &DiffOptions::default())
+}
+
+pub fn jbsdiff40_32bit_with<O: OldData + ?Sized, W: Write>(
+    old: &O,
+    new: &[u8],
+    patch: W,
+    options: &DiffOptions,
+) -> BsDiffResult<()> {
+    jbsdiff40_sized(old, new, patch, false, options)
+}
+
+fn jbsdiff40_sized<O: OldData + ?Sized, W: Write>(
+    old: &O,
+    new: &[u8],
+    patch: W,
+    x64_bit: bool,
+    options: &DiffOptions,
+) -> BsDiffResult<()> {
+    options.validate()?;
     let mut ctrl_data = Vec::new();
     let mut diff_data = Vec::new();
     let mut extra_data = Vec::new();
     {
         let streams = JBsDiffStreams {
-            ctrl_stream: BzEncoder::new(&mut ctrl_data, Compression::Best),
-            diff_stream: BzEncoder::new(&mut diff_data, Compression::Best),
-            extra_stream: BzEncoder::new(&mut extra_data, Compression::Best),
+            ctrl_stream: BzEncoder::new(&mut ctrl_data, options.compression.bzip2()),
+            diff_stream: BzEncoder::new(&mut diff_data, options.compression.bzip2()),
+            extra_stream: BzEncoder::new(&mut extra_data, options.compression.bzip2()),
         };
 
         let req = BsDiffRequest {
             data: streams,
-            ctrl_stream: |data, buffer| data.ctrl_stream.write_all(buffer),
-            diff_stream: |data, buffer| data.diff_stream.write_all(buffer),
-            extra_stream: |data, buffer| data.extra_stream.write_all(buffer),
+            ctrl_stream: |data, buffer| Ok(data.ctrl_stream.write_all(buffer)?),
+            diff_stream: |data, buffer| Ok(data.diff_stream.write_all(buffer)?),
+            extra_stream: |data, buffer| Ok(data.extra_stream.write_all(buffer)?),
         };
 
-        bsdiff_internal(old, new, req, x64_bit)?;
+        bsdiff_internal(old, new, req, x64_bit, options)?;
     }
 
-    patch.write_all(MAGIC_NUMBER_BSDIFF_40.as_bytes())?;
-    patch.write_u64::<LittleEndian>(ctrl_data.len() as u64)?;
-    patch.write_u64::<LittleEndian>(diff_data.len() as u64)?;
-    patch.write_u64::<LittleEndian>(new.len() as u64)?;
+    let sections = JBsDiffStreams {
+        ctrl_stream: ctrl_data,
+        diff_stream: diff_data,
+        extra_stream: extra_data,
+    };
+    write_jbsdiff40(patch, new.len(), &sections)?;
+    Ok(())
+}
 
-    patch.write_all(&ctrl_data)?;
-    patch.write_all(&diff_data)?;
-    patch.write_all(&extra_data)?;
+/// Writes the header of a `jbsdiff40` patch followed by its compressed sections
+fn write_jbsdiff40<W: Write>(
+    mut patch: W,
+    new_len: usize,
+    sections: &JBsDiffStreams<Vec<u8>>,
+) -> io::Result<()> {
+    patch.write_all(MAGIC_NUMBER_BSDIFF_40.as_bytes())?;
+    patch.write_u64::<LittleEndian>(sections.ctrl_stream.len() as u64)?;
+    patch.write_u64::<LittleEndian>(sections.diff_stream.len() as u64)?;
+    patch.write_u64::<LittleEndian>(new_len as u64)?;
 
+    patch.write_all(&sections.ctrl_stream)?;
+    patch.write_all(&sections.diff_stream)?;
+    patch.write_all(&sections.extra_stream)?;
     Ok(())
 }
 
-#[cfg(not(feature = "c_backend"))]
-pub fn jbsdiff40_vec(old: &[u8], new: &[u8]) -> BsDiffResult<Vec<u8>> {
+pub fn jbsdiff40_vec<O: OldData + ?Sized>(old: &O, new: &[u8]) -> BsDiffResult<Vec<u8>> {
     let mut patch = Vec::new();
     jbsdiff40(old, new, &mut patch)?;
     Ok(patch)
 }
 
-
-#[cfg(not(feature = "c_backend"))]
 pub fn jbspatch40_32bit<W: Write, R: Read>(old: &[u8], new: W, patch: R) -> BsDiffResult<()> {
-    jbspatch40_sized(old, new, patch, false)
+    jbspatch40_sized(old, new, patch, false, &PatchOptions::default())
+}
+
+pub fn jbspatch40_32bit_with<W: Write, R: Read>(
+    old: &[u8],
+    new: W,
+    patch: R,
+    options: &PatchOptions,
+) -> BsDiffResult<()> {
+    jbspatch40_sized(old, new, patch, false, options)
 }
 
-#[cfg(not(feature = "c_backend"))]
 pub fn jbspatch40<W: Write, R: Read>(old: &[u8], new: W, patch: R) -> BsDiffResult<()> {
-    jbspatch40_sized(old, new, patch, true)
+    jbspatch40_sized(old, new, patch, true, &PatchOptions::default())
 }
 
-#[cfg(not(feature = "c_backend"))]
-fn jbspatch40_sized<W: Write, R: Read>(old: &[u8], new: W, mut patch: R, x64_bit: bool) -> BsDiffResult<()> {
+pub fn jbspatch40_with<W: Write, R: Read>(
+    old: &[u8],
+    new: W,
+    patch: R,
+    options: &PatchOptions,
+) -> BsDiffResult<()> {
+    jbspatch40_sized(old, new, patch, true, options)
+}
+
+fn jbspatch40_sized<W: Write, R: Read>(
+    old: &[u8],
+    new: W,
+    mut patch: R,
+    x64_bit: bool,
+    options: &PatchOptions,
+) -> BsDiffResult<()> {
     let mut header = [0u8; 32];
-    patch.read_exact(&mut header).unwrap();
-    assert_eq!(&header[..8], MAGIC_NUMBER_BSDIFF_40.as_bytes());
+    patch.read_exact(&mut header).map_err(BsDiffError::from_header)?;
+    if &header[..8] != MAGIC_NUMBER_BSDIFF_40.as_bytes() {
+        return Err(BsDiffError::BadMagic);
+    }
     let mut header_iter = &header[8..];
+    let ctrl_len = header_iter.read_u64::<LittleEndian>()?;
+    let diff_len = header_iter.read_u64::<LittleEndian>()?;
+    let out_len = header_iter.read_u64::<LittleEndian>()?;
 
-    let ctrl_len = header_iter.read_u64::<LittleEndian>().unwrap() as usize;
-    let mut ctrl_data = vec![0u8; ctrl_len].into_boxed_slice();
-    patch.read_exact(&mut ctrl_data).unwrap();
-    let ctrl_stream = BzDecoder::new(&*ctrl_data);
-
-    let diff_len = header_iter.read_u64::<LittleEndian>().unwrap() as usize;
-    let mut diff_data = vec![0u8; diff_len].into_boxed_slice();
-    patch.read_exact(&mut diff_data).unwrap();
-    let diff_stream = BzDecoder::new(&*diff_data);
-
-    let mut extra_data = Vec::new();
-    patch.read_to_end(&mut extra_data).unwrap();
-    let extra_stream = BzDecoder::new(&*extra_data);
-    let out_len = header_iter.read_u64::<LittleEndian>().unwrap() as usize;
+    options.limits.check_new_size(out_len)?;
+    let sections = read_sections(patch, ctrl_len, diff_len, options)?;
+    let out_len = declared_len(out_len)?;
+    let held = sections.len();
 
     let streams = JBsDiffStreams {
-        ctrl_stream,
-        diff_stream,
-        extra_stream,
+        ctrl_stream: BzDecoder::new(&*sections.ctrl_stream),
+        diff_stream: BzDecoder::new(&*sections.diff_stream),
+        extra_stream: BzDecoder::new(&*sections.extra_stream),
     };
 
     let req = BsPatchRequest {
         data: streams,
-        ctrl_stream: |data, buffer| data.ctrl_stream.read_exact(buffer),
-        diff_stream: |data, buffer| data.diff_stream.read_exact(buffer),
-        extra_stream: |data, buffer| data.extra_stream.read_exact(buffer),
+        ctrl_stream: |data, buffer| {
+            data.ctrl_stream
+                .read_exact(buffer)
+                .map_err(BsDiffError::from_compressed)
+        },
+        diff_stream: |data, buffer| {
+            data.diff_stream
+                .read_exact(buffer)
+                .map_err(BsDiffError::from_compressed)
+        },
+        extra_stream: |data, buffer| {
+            data.extra_stream
+                .read_exact(buffer)
+                .map_err(BsDiffError::from_compressed)
+        },
     };
 
-    bspatch_internal(old, new, out_len, req, x64_bit)?;
+    bspatch_internal(old, new, out_len, req, x64_bit, held, options)?;
     Ok(())
 }
 
-#[cfg(not(feature = "c_backend"))]
+/// Converts a length declared in a patch header, which may not fit the address space
+pub(crate) fn declared_len(len: u64) -> BsDiffResult<usize> {
+    usize::try_from(len).map_err(|_| BsDiffError::LimitExceeded {
+        requested: len,
+        limit: usize::MAX as u64,
+    })
+}
+
+/// Reads a section of `len` bytes, growing the buffer only as far as the patch actually goes
+fn read_section<R: Read>(patch: &mut R, len: u64) -> BsDiffResult<Vec<u8>> {
+    let mut data = Vec::new();
+    patch.take(len).read_to_end(&mut data)?;
+    if (data.len() as u64) < len {
+        return Err(BsDiffError::from_compressed(io::ErrorKind::UnexpectedEof.into()));
+    }
+    Ok(data)
+}
+
+/// Reads the compressed ctrl, diff and extra sections following the header of a patch
+fn read_sections<R: Read>(
+    mut patch: R,
+    ctrl_len: u64,
+    diff_len: u64,
+    options: &PatchOptions,
+) -> BsDiffResult<JBsDiffStreams<Vec<u8>>> {
+    let limits = &options.limits;
+    let sections_len = ctrl_len.saturating_add(diff_len);
+    limits.check_memory(sections_len)?;
+
+    let ctrl_data = read_section(&mut patch, ctrl_len)?;
+    let diff_data = read_section(&mut patch, diff_len)?;
+
+    // The extra section runs to the end of the patch, so only read up to what the limit allows
+    let extra_limit = limits.max_memory - sections_len;
+    let mut extra_data = Vec::new();
+    patch
+        .take(extra_limit.saturating_add(1))
+        .read_to_end(&mut extra_data)?;
+    limits.check_memory(sections_len + extra_data.len() as u64)?;
+
+    Ok(JBsDiffStreams {
+        ctrl_stream: ctrl_data,
+        diff_stream: diff_data,
+        extra_stream: extra_data,
+    })
+}
+
 pub fn jbspatch40_vec<R: Read>(old: &[u8], patch: R) -> BsDiffResult<Vec<u8>> {
     let mut new = Vec::new();
     jbspatch40(old, &mut new, patch)?;
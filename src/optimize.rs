@@ -0,0 +1,284 @@
+use crate::rust::trims;
+use crate::{bsdiff43_encoder, bsdiff_raw_with, write_jbsdiff40, JBsDiffStreams};
+use crate::{BsDiffResult, Codec, Compression, DiffOptions, OldData, OldIndex};
+use byteorder::{ByteOrder, LittleEndian};
+use bzip2::write::BzEncoder;
+use std::io::Write;
+
+/// A patch format made from a raw patch, with the settings worth trying for it besides the
+/// matching parameters
+trait Format {
+    /// Writes the raw patch that `encode` turns into a patch of this format
+    fn diff<O: OldData + ?Sized>(
+        old: &O,
+        new: &[u8],
+        raw: &mut Vec<u8>,
+        options: &DiffOptions,
+    ) -> BsDiffResult<()> {
+        bsdiff_raw_with(old, new, raw, options)
+    }
+
+    /// Whether `diff` follows the matching parameters. The C backend ignores them, so with it
+    /// every parameter set would only run the same diff again.
+    fn matches_with_options() -> bool {
+        !cfg!(feature = "c_backend")
+    }
+
+    /// Writes the patch made of `raw` with the codec and compression of `options`
+    fn encode(
+        raw: &[u8],
+        new_len: usize,
+        patch: &mut Vec<u8>,
+        options: &DiffOptions,
+    ) -> BsDiffResult<()>;
+
+    /// The codecs and bzip2 levels to try. Formats without compression have just one.
+    fn encodings() -> Vec<(Codec, Compression)> {
+        bzip2_levels()
+    }
+}
+
+fn bzip2_levels() -> Vec<(Codec, Compression)> {
+    (1..=9)
+        .rev()
+        .map(|level| (Codec::Bzip2, Compression::new(level)))
+        .collect()
+}
+
+struct Raw;
+
+impl Format for Raw {
+    fn encode(raw: &[u8], _: usize, patch: &mut Vec<u8>, _: &DiffOptions) -> BsDiffResult<()> {
+        patch.extend_from_slice(raw);
+        Ok(())
+    }
+
+    fn encodings() -> Vec<(Codec, Compression)> {
+        let default = DiffOptions::default();
+        vec![(default.codec, default.compression)]
+    }
+}
+
+struct BsDiff43;
+
+impl Format for BsDiff43 {
+    fn encode(
+        raw: &[u8],
+        new_len: usize,
+        patch: &mut Vec<u8>,
+        options: &DiffOptions,
+    ) -> BsDiffResult<()> {
+        let mut compress = bsdiff43_encoder(patch, new_len, options)?;
+        compress.write_all(raw)?;
+        compress.finish()?;
+        Ok(())
+    }
+
+    /// Besides bzip2, the strongest setting of every codec compiled in
+    fn encodings() -> Vec<(Codec, Compression)> {
+        let default = DiffOptions::default().compression;
+        let codecs = [
+            Codec::Xz(9),
+            Codec::Zstd(19),
+            Codec::Brotli(11),
+            Codec::Gzip(9),
+        ];
+        let others = codecs.iter().filter(|codec| codec.available());
+        bzip2_levels()
+            .into_iter()
+            .chain(others.map(|&codec| (codec, default)))
+            .collect()
+    }
+}
+
+struct JBsDiff40;
+
+impl Format for JBsDiff40 {
+    /// `jbsdiff40` patches are always made by the rust backend
+    fn diff<O: OldData + ?Sized>(
+        old: &O,
+        new: &[u8],
+        raw: &mut Vec<u8>,
+        options: &DiffOptions,
+    ) -> BsDiffResult<()> {
+        crate::rust::bsdiff_raw_with(old, new, raw, options)
+    }
+
+    fn matches_with_options() -> bool {
+        true
+    }
+
+    fn encode(
+        raw: &[u8],
+        new_len: usize,
+        patch: &mut Vec<u8>,
+        options: &DiffOptions,
+    ) -> BsDiffResult<()> {
+        let compress = |section: &[u8]| -> BsDiffResult<Vec<u8>> {
+            let mut encoder = BzEncoder::new(Vec::new(), options.compression.bzip2());
+            encoder.write_all(section)?;
+            Ok(encoder.finish()?)
+        };
+        let sections = split(raw);
+        let sections = JBsDiffStreams {
+            ctrl_stream: compress(&sections.ctrl_stream)?,
+            diff_stream: compress(&sections.diff_stream)?,
+            extra_stream: compress(&sections.extra_stream)?,
+        };
+        write_jbsdiff40(patch, new_len, &sections)?;
+        Ok(())
+    }
+}
+
+/// Separates the control entries, diff bytes and extra bytes of a raw patch
+fn split(mut raw: &[u8]) -> JBsDiffStreams<Vec<u8>> {
+    let mut sections = JBsDiffStreams {
+        ctrl_stream: Vec::new(),
+        diff_stream: Vec::new(),
+        extra_stream: Vec::new(),
+    };
+    while !raw.is_empty() {
+        let (ctrl, rest) = raw.split_at(24);
+        let diff_len = LittleEndian::read_i64(&ctrl[..8]) as usize;
+        let extra_len = LittleEndian::read_i64(&ctrl[8..16]) as usize;
+        let (diff, rest) = rest.split_at(diff_len);
+        let (extra, rest) = rest.split_at(extra_len);
+        sections.ctrl_stream.extend_from_slice(ctrl);
+        sections.diff_stream.extend_from_slice(diff);
+        sections.extra_stream.extend_from_slice(extra);
+        raw = rest;
+    }
+    sections
+}
+
+/// The matching parameters `optimize` tries, each of them with every encoding
+fn parameter_sets() -> Vec<DiffOptions> {
+    let smallest = DiffOptions::smallest();
+    vec![
+        DiffOptions::default(),
+        DiffOptions {
+            min_gain: 12,
+            ..smallest.clone()
+        },
+        DiffOptions {
+            min_gain: 16,
+            ..smallest.clone()
+        },
+        DiffOptions {
+            mismatch_penalty: 2,
+            ..smallest.clone()
+        },
+        smallest,
+    ]
+}
+
+///
+/// Diffs with every parameter set, encodes each raw patch in every encoding, writes the smallest
+/// patch to `patch` and returns the options it was made with. The suffix array of `old` is built
+/// only once.
+///
+fn optimize<F: Format, O: OldData + ?Sized, W: Write>(
+    old: &O,
+    new: &[u8],
+    mut patch: W,
+) -> BsDiffResult<DiffOptions> {
+    let mut built = None;
+    // Trimming only happens without an index, and is only worth trying where it applies. The C
+    // backend ignores it, but diffing the bytes spares it an index it would not use either.
+    let trim: &[bool] = if !F::matches_with_options() {
+        &[true]
+    } else if old.old_index().is_none() && trims(old.old_bytes(), new) {
+        &[false, true]
+    } else {
+        &[false]
+    };
+    let parameter_sets = if F::matches_with_options() {
+        parameter_sets()
+    } else {
+        vec![DiffOptions::default()]
+    };
+
+    let mut best: Option<(Vec<u8>, DiffOptions)> = None;
+    let mut raw = Vec::new();
+    let mut candidate = Vec::new();
+    for parameters in parameter_sets {
+        for &trimmed in trim {
+            let parameters = DiffOptions {
+                trim: trimmed,
+                ..parameters.clone()
+            };
+            raw.clear();
+            if trimmed {
+                F::diff(old.old_bytes(), new, &mut raw, &parameters)?;
+            } else {
+                let index = match old.old_index() {
+                    Some(index) => index,
+                    None => built.get_or_insert_with(|| OldIndex::new(old.old_bytes())),
+                };
+                F::diff(index, new, &mut raw, &parameters)?;
+            }
+            for &(codec, compression) in &F::encodings() {
+                let options = DiffOptions {
+                    compression,
+                    codec,
+                    ..parameters.clone()
+                };
+                candidate.clear();
+                F::encode(&raw, new.len(), &mut candidate, &options)?;
+                let smaller = match &best {
+                    Some((patch, _)) => candidate.len() < patch.len(),
+                    None => true,
+                };
+                if smaller {
+                    best = Some((std::mem::take(&mut candidate), options));
+                }
+            }
+        }
+    }
+
+    let (smallest, options) = match best {
+        Some(best) => best,
+        None => unreachable!("There is always a parameter set, a trim setting and an encoding"),
+    };
+    patch.write_all(&smallest)?;
+    Ok(options)
+}
+
+///
+/// Writes the smallest raw patch found across a range of `DiffOptions`, for when patch size
+/// matters far more than diff time. Returns the options chosen, with which `bsdiff_raw_with`
+/// makes the same patch again.
+///
+pub fn bsdiff_raw_optimize<O: OldData + ?Sized, W: Write>(
+    old: &O,
+    new: &[u8],
+    patch: W,
+) -> BsDiffResult<DiffOptions> {
+    optimize::<Raw, _, _>(old, new, patch)
+}
+
+///
+/// Writes the smallest `bsdiff43` patch found across a range of `DiffOptions` and compression
+/// levels. Returns the options chosen, with which `bsdiff43_with` makes the same patch again.
+/// Codecs compiled in besides bzip2 are tried too, so the patch may need a `Codec` other than
+/// `Codec::Bzip2` and with it this crate to apply.
+///
+pub fn bsdiff43_optimize<O: OldData + ?Sized, W: Write>(
+    old: &O,
+    new: &[u8],
+    patch: W,
+) -> BsDiffResult<DiffOptions> {
+    optimize::<BsDiff43, _, _>(old, new, patch)
+}
+
+///
+/// Writes the smallest `jbsdiff40` patch found across a range of `DiffOptions` and compression
+/// levels. Returns the options chosen, with which `jbsdiff40_with` makes the same patch again.
+///
+pub fn jbsdiff40_optimize<O: OldData + ?Sized, W: Write>(
+    old: &O,
+    new: &[u8],
+    patch: W,
+) -> BsDiffResult<DiffOptions> {
+    optimize::<JBsDiff40, _, _>(old, new, patch)
+}
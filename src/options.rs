@@ -0,0 +1,208 @@
+use crate::{BsDiffError, BsDiffResult};
+use crate::Codec;
+
+///
+/// Settings for generating a patch.
+///
+/// `DiffOptions::default()` matches the way the original bsdiff does, except that it carries
+/// long matches over (`reuse_len`) and trims shared ends (`trim`), so its patches are not byte
+/// for byte those of the C bsdiff. Even with both turned off, ties between equally long matches
+/// are broken differently. `fast` and `smallest` trade patch size for diff time in either
+/// direction. Every setting produces patches that any bspatch applies. The C backend only
+/// honours `compression`.
+///
+#[derive(Debug, Clone)]
+pub struct DiffOptions {
+    /// How many bytes a match must cover beyond what the current alignment already predicts
+    /// before the scan switches to it. Lower values switch more eagerly and write more control
+    /// entries, higher values stay with an alignment longer. bsdiff uses 8.
+    pub min_gain: usize,
+    /// What a mismatching byte costs when a match is extended forwards or backwards, against 1
+    /// for every matching byte. Higher values keep extensions short and move more bytes to the
+    /// extra block. bsdiff uses 1.
+    pub mismatch_penalty: u32,
+    /// Matches longer than this are carried over to the next byte instead of searched for again.
+    /// Lower values diff repetitive data faster, `usize::MAX` searches at every byte as bsdiff
+    /// does, which can take time quadratic in the length of a match.
+    pub reuse_len: usize,
+    /// How many other occurrences of a match are weighed before the scan switches to it. The one
+    /// whose surrounding bytes best match the new data is taken, which leaves more zeros in the
+    /// diff block. 0 takes whichever occurrence the search finds first, as bsdiff does.
+    pub candidates: usize,
+    /// How many bytes past the point where the scan would switch to a new match are searched as
+    /// well. A later match is taken instead if it reaches further into the new data. 0 switches
+    /// at the first good enough match, as bsdiff does.
+    pub lookahead: usize,
+    /// Whether to diff only the differing middle of large files that share long ends,
+    /// without sorting the rest of the old data. Never applies when an `OldIndex` is passed.
+    pub trim: bool,
+    /// How many equally sized parts the new data is split into, each matched on its own and
+    /// concurrently with the `parallel` feature. More parts make slightly larger patches.
+    pub segments: usize,
+    /// The bzip2 level of `bsdiff43` and `jbsdiff40` patches, from 1 (`Compression::fast()`) to
+    /// 9 (`Compression::best()`). Raw patches are not compressed, and `bsdiff43` patches only
+    /// use it with `Codec::Bzip2`: every other codec carries its own level and ignores this one.
+    ///
+    /// bzip2 has no separate block size: the level is the size of its blocks in units of 100 KB.
+    /// Larger blocks only shrink patches whose diff and extra data repeat more than a block
+    /// apart, since runs of zeros are packed before they are split into blocks, so most patches
+    /// come out the same size at every level above 1. In exchange they take up to three times as
+    /// long to compress and need more memory on both ends: 400 KB plus 8 times the block size to
+    /// compress and 100 KB plus 4 times the block size to decompress, 7.6 MB and 3.7 MB at 9.
+    pub compression: Compression,
+    /// How `bsdiff43` patches are compressed. Anything but `Codec::Bzip2` writes a variant
+    /// header that only this crate reads. `jbsdiff40` patches are always bzip2.
+    pub codec: Codec,
+}
+
+impl DiffOptions {
+    /// Rejects settings without a meaning, before anything is written
+    pub(crate) fn validate(&self) -> BsDiffResult<()> {
+        match self.compression.level() {
+            1..=9 => self.codec.validate(),
+            _ => Err(BsDiffError::InvalidOptions("bzip2 levels run from 1 to 9")),
+        }
+    }
+
+    /// Diffs as quickly as possible, for patches that are thrown away soon
+    pub fn fast() -> DiffOptions {
+        DiffOptions {
+            reuse_len: 16,
+            compression: Compression::fast(),
+            ..DiffOptions::default()
+        }
+    }
+
+    /// Spends more time matching for a smaller patch
+    pub fn smallest() -> DiffOptions {
+        DiffOptions {
+            reuse_len: 4096,
+            candidates: 16,
+            lookahead: 4,
+            trim: false,
+            ..DiffOptions::default()
+        }
+    }
+}
+
+impl Default for DiffOptions {
+    fn default() -> Self {
+        DiffOptions {
+            min_gain: 8,
+            mismatch_penalty: 1,
+            reuse_len: 64,
+            candidates: 0,
+            lookahead: 0,
+            trim: true,
+            segments: 1,
+            compression: Compression::best(),
+            codec: Codec::Bzip2,
+        }
+    }
+}
+
+///
+/// A bzip2 compression level, from 1 to 9. Levels outside of that range are rejected with
+/// `BsDiffError::InvalidOptions` when diffing starts.
+///
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Compression(u32);
+
+impl Compression {
+    pub fn new(level: u32) -> Compression {
+        Compression(level)
+    }
+
+    /// Level 1, the smallest blocks
+    pub fn fast() -> Compression {
+        Compression(1)
+    }
+
+    /// Level 9, the largest blocks
+    pub fn best() -> Compression {
+        Compression(9)
+    }
+
+    pub fn level(&self) -> u32 {
+        self.0
+    }
+
+    pub(crate) fn bzip2(self) -> bzip2::Compression {
+        bzip2::Compression::new(self.0)
+    }
+}
+
+///
+/// Settings for applying a patch.
+///
+#[derive(Debug, Clone, Default)]
+pub struct PatchOptions {
+    /// Reject any control entry that reads outside of the old data or seeks outside of it.
+    ///
+    /// The reference bspatch silently treats old bytes past either end as zero, which is what
+    /// happens when this is `false`. Negative lengths and arithmetic overflow are always rejected.
+    /// Only the rust backend enforces this.
+    pub strict: bool,
+    /// Caps on what the patch may ask for, checked before anything is allocated
+    pub limits: PatchLimits,
+}
+
+impl PatchOptions {
+    /// Options for patches from untrusted sources
+    pub fn strict() -> PatchOptions {
+        PatchOptions {
+            strict: true,
+            ..PatchOptions::default()
+        }
+    }
+}
+
+///
+/// Resource limits for applying a patch.
+/// Every limit defaults to unlimited. A patch that exceeds one fails with `BsDiffError::LimitExceeded`.
+///
+#[derive(Debug, Clone)]
+pub struct PatchLimits {
+    /// Largest output a patch header may declare
+    pub max_new_size: u64,
+    /// Largest diff or extra run a single control entry may describe
+    pub max_op_len: u64,
+    /// Largest amount of memory the patch reader may hold in buffers at once, in total: the
+    /// compressed sections of a `jbsdiff40`, `BSDIFF40` or `BSDF2` patch, the windows onto them
+    /// of `jbspatch40_seekable`, the output buffer of a backend that needs one, and the
+    /// scratch buffer diff and extra runs stream through. The decompressors' own state is not
+    /// counted.
+    pub max_memory: u64,
+}
+
+impl Default for PatchLimits {
+    fn default() -> Self {
+        PatchLimits {
+            max_new_size: u64::MAX,
+            max_op_len: u64::MAX,
+            max_memory: u64::MAX,
+        }
+    }
+}
+
+fn check(requested: u64, limit: u64) -> BsDiffResult<()> {
+    if requested > limit {
+        Err(BsDiffError::LimitExceeded { requested, limit })
+    } else {
+        Ok(())
+    }
+}
+
+impl PatchLimits {
+    pub(crate) fn check_new_size(&self, size: u64) -> BsDiffResult<()> {
+        check(size, self.max_new_size)
+    }
+
+    pub(crate) fn check_op_len(&self, len: u64) -> BsDiffResult<()> {
+        check(len, self.max_op_len)
+    }
+
+    pub(crate) fn check_memory(&self, bytes: u64) -> BsDiffResult<()> {
+        check(bytes, self.max_memory)
+    }
+}
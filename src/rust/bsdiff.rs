@@ -1,251 +1,518 @@
 #![allow(non_snake_case)]
 
-use crate::BsDiffResult;
+use super::index::{with_index, OldData, OldIndex};
+use super::suffix_index::Entries;
+use super::sais::SaIndex;
+use super::simd::{self, Kernel};
+use crate::{BsDiffResult, DiffOptions};
 use byteorder::{LittleEndian, WriteBytesExt};
-use std::cmp::{min, Ordering};
+use std::cmp::min;
 use std::io::Write;
 use std::convert::TryFrom;
-fn split(I: &mut [isize], V: &mut [isize], start: isize, len: isize, h: isize) {
-    if len < 16 {
-        let mut k = start;
-        while k < start + len {
-            let mut j = 1;
-            let mut x = V[(I[k as usize] + h) as usize];
-            let mut i = 1;
-            while k + i < start + len {
-                let value = &V[(I[(k + i) as usize] + h) as usize];
-                if *value < x {
-                    x = *value;
-                    j = 0;
-                }
-                if *value == x {
-                    I.swap((k + j) as usize, (k + i) as usize);
-                    j += 1;
-                }
-                i += 1;
-            }
 
-            for i in 0..j {
-                V[(I[(k + i) as usize]) as usize] = k + j - 1;
-                if j == 1 {
-                    I[k as usize] = -1;
-                }
-            }
-            k += j;
-        }
-        return;
+pub(crate) fn matchlen(old: &[u8], new: &[u8]) -> i64 {
+    simd::matchlen(old, new) as i64
+}
+
+/// Levels of the binary search in `search` kept in a `SearchTree`
+const TREE_DEPTH: u32 = 16;
+
+#[derive(Clone, Copy)]
+struct TreeNode {
+    /// The first 8 bytes of the suffix, big endian and zero padded
+    prefix: u64,
+    suffix: usize,
+}
+
+///
+/// The suffixes compared in the first levels of every `search`, stored breadth first along with
+/// their leading bytes. The top of the search then stays in cache, and most comparisons there
+/// are decided without touching the suffix array or the old data.
+///
+pub(crate) struct SearchTree {
+    nodes: Vec<TreeNode>,
+}
+
+impl SearchTree {
+    pub(crate) fn new<Idx: SaIndex>(I: &[Idx], old: &[u8]) -> Self {
+        let depth = min(TREE_DEPTH, usize::BITS - old.len().leading_zeros());
+        let mut tree = SearchTree {
+            nodes: vec![TreeNode { prefix: 0, suffix: 0 }; (1 << depth) - 1],
+        };
+        tree.fill(I, old, 0, 0, old.len());
+        tree
     }
 
-    let x = V[(I[(start + len / 2) as usize] + h) as usize];
-    let mut jj = 0;
-    let mut kk = 0;
-    for i in start..(start + len) {
-        match V[(I[i as usize] + h) as usize].cmp(&x) {
-            Ordering::Less => jj += 1,
-            Ordering::Equal => kk += 1,
-            Ordering::Greater => {}
+    /// Stores the middle of `I[start..=end]` at `node`, with the two halves as its children
+    fn fill<Idx: SaIndex>(&mut self, I: &[Idx], old: &[u8], node: usize, start: usize, end: usize) {
+        if node >= self.nodes.len() || end - start < 2 {
+            return;
         }
+        let middle = start + (end - start) / 2;
+        let suffix = I[middle].to_usize();
+        self.nodes[node] = TreeNode {
+            prefix: load_prefix(&old[suffix..]),
+            suffix,
+        };
+        self.fill(I, old, 2 * node + 1, start, middle);
+        self.fill(I, old, 2 * node + 2, middle, end);
     }
-    jj += start;
-    kk += jj;
-
-    let mut i = start;
-    let mut j = 0;
-    let mut k = 0;
-    while i < jj {
-        match V[(I[i as usize] + h) as usize].cmp(&x) {
-            Ordering::Less => i += 1,
-            Ordering::Equal => {
-                I.swap(i as usize, (j + jj) as usize);
-                j += 1;
-            }
-            Ordering::Greater => {
-                I.swap(i as usize, (k + kk) as usize);
-                k += 1;
+}
+
+fn load_prefix(data: &[u8]) -> u64 {
+    let mut bytes = [0u8; 8];
+    let len = min(8, data.len());
+    bytes[..len].copy_from_slice(&data[..len]);
+    u64::from_be_bytes(bytes)
+}
+
+///
+/// Finds the suffix of `old` sharing the longest prefix with `new` by binary search over `I`.
+///
+/// Every suffix between two others shares at least the prefix those two have in common with
+/// `new`, so each comparison starts after the shorter of the matches at either bound. This picks
+/// the same suffix as comparing from the first byte every time.
+///
+/// A suffix that is a proper prefix of `new` orders before it, as in the suffix array. The
+/// original bsdiff compares only the common length and counts it as not less, which steers the
+/// search to ever shorter suffixes when `old` ends in a long run, missing the longest match.
+///
+pub(crate) fn search<Idx: SaIndex>(
+    tree: &SearchTree,
+    I: &[Idx],
+    old: &[u8],
+    new: &[u8],
+    pos: &mut isize,
+) -> i64 {
+    let (rank, len) = search_rank(tree, I, old, new);
+    *pos = I[rank].to_usize() as isize;
+    len as i64
+}
+
+/// Like `search`, returning the index of the suffix in `I` rather than its position in `old`
+fn search_rank<Idx: SaIndex>(
+    tree: &SearchTree,
+    I: &[Idx],
+    old: &[u8],
+    new: &[u8],
+) -> (usize, usize) {
+    let mut start = 0;
+    let mut end = old.len();
+    let mut start_len = matchlen(&old[I[start].to_usize()..], new) as usize;
+    let mut end_len = matchlen(&old[I[end].to_usize()..], new) as usize;
+    let new_prefix = load_prefix(new);
+    let mut node = 0;
+    while end - start >= 2 {
+        let middle = start + (end - start) / 2;
+        let mut known = min(start_len, end_len);
+        let suffix;
+        let mut decided = None;
+        if node < tree.nodes.len() {
+            let cached = tree.nodes[node];
+            suffix = cached.suffix;
+            let limit = min(old.len() - suffix, new.len());
+            let common = min((cached.prefix ^ new_prefix).leading_zeros() as usize / 8, limit);
+            if common < 8 {
+                // The first differing byte, if any, decides the order of the whole prefixes
+                let less = if common < limit {
+                    cached.prefix < new_prefix
+                } else {
+                    old.len() - suffix < new.len()
+                };
+                decided = Some((common, less));
+            } else {
+                known = known.max(8);
             }
+        } else {
+            suffix = I[middle].to_usize();
         }
-    }
-
-    while jj + j < kk {
-        if V[(I[(jj + j) as usize] + h) as usize] == x {
-            j += 1;
+        let (len, less) = decided.unwrap_or_else(|| {
+            let len = known + matchlen(&old[suffix + known..], &new[known..]) as usize;
+            let less = if len < min(old.len() - suffix, new.len()) {
+                old[suffix + len] < new[len]
+            } else {
+                old.len() - suffix < new.len()
+            };
+            (len, less)
+        });
+
+        if less {
+            start = middle;
+            start_len = len;
+            node = 2 * node + 2;
         } else {
-            I.swap((jj + j) as usize, (kk + k) as usize);
-            k += 1;
+            end = middle;
+            end_len = len;
+            node = 2 * node + 1;
         }
     }
 
-    if jj > start {
-        split(I, V, start, jj - start, h)
-    }
-    for i in 0..(kk - jj) {
-        V[I[(jj + i) as usize] as usize] = kk - 1
+    if start_len > end_len {
+        (start, start_len)
+    } else {
+        (end, end_len)
     }
+}
 
-    if jj == kk - 1 {
-        I[jj as usize] = -1
+/// Bytes on either side of a match compared when choosing between its occurrences
+const CONTEXT_LEN: usize = 64;
+
+///
+/// The occurrence of the `len` byte match at the start of `new[scan..]` that best fits its
+/// surroundings, among the suffix found by `search` and up to `candidates` neighbours on either
+/// side of it in `I`. Every suffix sharing the whole match is adjacent to the one found.
+///
+/// An occurrence scores the bytes that also match in the `CONTEXT_LEN` bytes before and after
+/// it, which become zeros in the diff block when the match is extended. Ties go to the
+/// occurrence closest to `target`, where the previous alignment would read, for a short seek.
+///
+#[allow(clippy::too_many_arguments)]
+fn best_candidate<Idx: SaIndex>(
+    kernel: Kernel,
+    tree: &SearchTree,
+    I: &[Idx],
+    old: &[u8],
+    new: &[u8],
+    scan: usize,
+    candidates: usize,
+    target: isize,
+) -> (usize, usize) {
+    let (rank, len) = search_rank(tree, I, old, &new[scan..]);
+    if len == 0 {
+        return (I[rank].to_usize(), 0);
     }
-    if start + len > kk {
-        split(I, V, kk, start + len - kk, h)
+    let fit = |pos: usize| {
+        let before = min(min(pos, scan), CONTEXT_LEN);
+        let after = &new[scan + len..min(scan + len + CONTEXT_LEN, new.len())];
+        let score = kernel.count_equal(&old[pos - before..pos], &new[scan - before..scan])
+            + kernel.count_equal(&old[pos + len..], after);
+        (score, std::cmp::Reverse((pos as isize - target).unsigned_abs()))
+    };
+    let shares_match =
+        |rank: usize| simd::matchlen(&old[I[rank].to_usize()..], &new[scan..]) >= len;
+
+    let mut best = I[rank].to_usize();
+    let mut best_fit = fit(best);
+    let lowest = rank.saturating_sub(candidates);
+    let highest = min(rank + candidates, I.len() - 1);
+    let below = (lowest..rank).rev().take_while(|&other| shares_match(other));
+    let above = (rank + 1..=highest).take_while(|&other| shares_match(other));
+    for other in below.chain(above) {
+        let pos = I[other].to_usize();
+        let other_fit = fit(pos);
+        if other_fit > best_fit {
+            best = pos;
+            best_fit = other_fit;
+        }
     }
+    (best, len)
 }
 
-fn qsufsort(I: &mut [isize], V: &mut [isize], old: &[u8]) {
-    let buckets: &mut [isize] = &mut [0; 256];
+pub struct BsDiffRequest<D> {
+    pub data: D,
+    pub ctrl_stream: fn(&mut D, &[u8]) -> BsDiffResult<()>,
+    pub diff_stream: fn(&mut D, &[u8]) -> BsDiffResult<()>,
+    pub extra_stream: fn(&mut D, &[u8]) -> BsDiffResult<()>,
+}
 
-    // each index n is the frequency that the u8 value n occurs in old
-    for i in 0..old.len() {
-        buckets[old[i] as usize] += 1
+/// Writes a control value, in 32-bit mode as jbsdiff does: the magnitude in the low four
+/// bytes and the sign in the top bit of the eighth.
+fn write_control(buffer: &mut Vec<u8>, value: i64, x64_bit: bool) -> std::io::Result<()> {
+    if x64_bit {
+        buffer.write_i64::<LittleEndian>(value)?;
+    } else {
+        let magnitude = i32::try_from(value.unsigned_abs()).map_err(|_| {
+            std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                "Control value does not fit in a 32-bit patch",
+            )
+        })?;
+        buffer.write_i32::<LittleEndian>(magnitude)?;
+        buffer.write_u8(0)?;
+        buffer.write_u8(0)?;
+        buffer.write_u8(0)?;
+        buffer.write_u8(if value < 0 { 0x80 } else { 0 })?;
     }
 
-    // adds the previous index
-    // index n is the cumulative frequency of the u8 value n and all smaller values
-    // assert_eq!(buckets[255], old.len() as i64);
-    for i in 1..256 {
-        buckets[i] += buckets[i - 1]
-    }
+    Ok(())
+}
 
-    // right shift 1 element, buckets[0] becomes 0
-    for i in (1..256).rev() {
-        buckets[i] = buckets[i - 1]
-    }
-    buckets[0] = 0;
-
-    // first step:
-    // buckets[n] is now cumulative frequency again because right shift + adding frequency of n
-    // means buckets[n] = cumulative of n - 1 + frequency of n => cumulative function again
-    //
-    // second step:
-    // I[n] is the index of the old array which contains the byte with the cumulative frequency of n
-    // in other words, `for x in 1..=old.len() {old[I[x]]}` returns old's elements in sorted order
-    // when n the sorted index, I[n + 1] is the index of old
-    for i in 0..old.len() {
-        buckets[old[i] as usize] += 1;
-        I[buckets[old[i] as usize] as usize] = i as isize;
-    }
-    I[0] = old.len() as isize;
+/// One control entry: copy `diff_len` bytes from old with differences added, insert
+/// `extra_len` bytes, then move the old position by `seek`.
+struct Control {
+    diff_len: usize,
+    extra_len: usize,
+    seek: i64,
+}
 
-    // V[i] is the inverse of I[i], when i is old's index, V[i] the sorted index
-    for i in 0..old.len() {
-        V[i] = buckets[old[i] as usize]
-    }
-    V[old.len()] = 0;
+/// Serializes control entries along with the diff and extra bytes they describe.
+struct PatchWriter<'a, D> {
+    old: &'a [u8],
+    new: &'a [u8],
+    req: BsDiffRequest<D>,
+    x64: bool,
+    buffer: Vec<u8>,
+    newpos: usize,
+    oldpos: i64,
+}
 
-    // if I[n] points to a unique byte value of old, now it points to -1
-    for i in 1..256 {
-        if buckets[i] == buckets[i - 1] + 1 {
-            I[buckets[i] as usize] = -1
+impl<'a, D> PatchWriter<'a, D> {
+    fn new(old: &'a [u8], new: &'a [u8], req: BsDiffRequest<D>, x64: bool) -> Self {
+        PatchWriter {
+            old,
+            new,
+            req,
+            x64,
+            buffer: vec![0u8; new.len()],
+            newpos: 0,
+            oldpos: 0,
         }
     }
-    I[0] = -1;
-
-    let mut h = 1;
-    while I[0] != -(old.len() as isize + 1) {
-        let mut len: usize = 0;
-        let mut i: usize = 0;
-        while i <= old.len() {
-            if I[i] < 0 {
-                len += (-I[i]) as usize;
-                i += (-I[i]) as usize;
-            } else {
-                if len != 0 {
-                    I[i - len] = -(len as isize);
-                }
-                len = V[I[i] as usize] as usize + 1 - i;
-                split(I, V, i as isize, len as isize, h);
-                i += len;
-                len = 0;
-            }
-        }
-        if len != 0 {
-            I[i - len] = -(len as isize);
+
+    fn write(&mut self, control: &Control) -> BsDiffResult<()> {
+        let BsDiffRequest {
+            data,
+            ctrl_stream,
+            diff_stream,
+            extra_stream,
+        } = &mut self.req;
+
+        // Write Control Data
+        let mut ctrl_buffer = Vec::with_capacity(3 * 8);
+        write_control(&mut ctrl_buffer, control.diff_len as i64, self.x64)?;
+        write_control(&mut ctrl_buffer, control.extra_len as i64, self.x64)?;
+        write_control(&mut ctrl_buffer, control.seek, self.x64)?;
+        ctrl_stream(data, &ctrl_buffer)?;
+
+        // Write Diff Data
+        let old = &self.old[self.oldpos as usize..];
+        let new = &self.new[self.newpos..];
+        for i in 0..control.diff_len {
+            self.buffer[i] = new[i].wrapping_sub(old[i]);
         }
+        diff_stream(data, &self.buffer[..control.diff_len])?;
 
-        h += h;
-    }
+        // Write Extra Data
+        let extra = &new[control.diff_len..control.diff_len + control.extra_len];
+        extra_stream(data, extra)?;
 
-    for i in 0..=old.len() {
-        I[V[i] as usize] = i as isize
+        self.newpos += control.diff_len + control.extra_len;
+        self.oldpos += control.diff_len as i64 + control.seek;
+        Ok(())
     }
 }
 
-fn matchlen(old: &[u8], new: &[u8]) -> i64 {
-    let mut i = 0;
-    let min_length = min(old.len(), new.len());
-    while i < min_length {
-        if old[i] != new[i] {
-            break;
+/// Old data shorter than this is always sorted whole, as trimming would gain little
+const TRIM_MIN_LEN: usize = 1 << 16;
+
+///
+/// Diffs `new` against `old`. When no index of `old` was passed in and the two share a long
+/// prefix and suffix, those are emitted as single control entries and only the differing
+/// middle is sorted and matched, see `Trim`.
+///
+pub(crate) fn bsdiff_internal<O: OldData + ?Sized, D>(
+    old: &O,
+    new: &[u8],
+    req: BsDiffRequest<D>,
+    x64: bool,
+    options: &DiffOptions,
+) -> BsDiffResult<D> {
+    if options.trim && old.old_index().is_none() {
+        if let Some(trim) = Trim::find(old.old_bytes(), new) {
+            return bsdiff_trimmed(old.old_bytes(), new, trim, req, x64, options);
         }
-        i += 1;
     }
-    return i as i64;
+    with_index(old, |old| {
+        let tree = old.suffix_index().search_tree();
+        match old.suffix_index().suffixes() {
+            Entries::Narrow(I) => bsdiff_with_index(old.old(), new, I, tree, req, x64, options),
+            Entries::Wide(I) => bsdiff_with_index(old.old(), new, I, tree, req, x64, options),
+        }
+    })
 }
 
-fn search(I: &[isize], old: &[u8], new: &[u8], start: usize, end: usize, pos: &mut isize) -> i64 {
-    if end - start < 2 {
-        let x = matchlen(&old[(I[start] as usize)..], new);
-        let y = matchlen(&old[(I[end] as usize)..], new);
+/// Whether `bsdiff_internal` diffs only the middle of `old` and `new` when allowed to trim
+pub(crate) fn trims(old: &[u8], new: &[u8]) -> bool {
+    Trim::find(old, new).is_some()
+}
 
-        if x > y {
-            *pos = I[start];
-            x
-        } else {
-            *pos = I[end];
-            y
+///
+/// The shared ends of `old` and `new`. The middle of `new` is matched against the middle of
+/// `old` widened by the length of the new middle on both sides, so data appended to a log can
+/// still be matched against the records before it.
+///
+struct Trim {
+    prefix: usize,
+    suffix: usize,
+    /// The part of `old` the middle of `new` is matched against
+    window: (usize, usize),
+}
+
+impl Trim {
+    /// Only trims when at most an eighth of a large `old` remains to be sorted
+    fn find(old: &[u8], new: &[u8]) -> Option<Trim> {
+        if old.len() < TRIM_MIN_LEN {
+            return None;
         }
-    } else {
-        let middle = start + (end - start) / 2;
-        let slice_len = min(old.len() - I[middle] as usize, new.len());
-        let lhs = &old[(I[middle] as usize)..(I[middle] as usize + slice_len)];
-        let rhs = &new[..slice_len];
-        if lhs < rhs {
-            search(I, old, new, middle, end, pos)
-        } else {
-            search(I, old, new, start, middle, pos)
+        let prefix = simd::matchlen(old, new);
+        let shortest = min(old.len(), new.len());
+        let suffix = simd::matchlen_back(&old[prefix..], &new[prefix..]).min(shortest - prefix);
+        let new_middle = new.len() - prefix - suffix;
+        let window = (
+            prefix.saturating_sub(new_middle),
+            min(old.len() - suffix + new_middle, old.len()),
+        );
+        if window.1 - window.0 > old.len() / 8 {
+            return None;
         }
+        Some(Trim {
+            prefix,
+            suffix,
+            window,
+        })
     }
 }
 
-pub struct BsDiffRequest<D> {
-    pub data: D,
-    pub ctrl_stream: fn(&mut D, &[u8]) -> BsDiffResult<()>,
-    pub diff_stream: fn(&mut D, &[u8]) -> BsDiffResult<()>,
-    pub extra_stream: fn(&mut D, &[u8]) -> BsDiffResult<()>,
-}
+fn bsdiff_trimmed<D>(
+    old: &[u8],
+    new: &[u8],
+    trim: Trim,
+    req: BsDiffRequest<D>,
+    x64: bool,
+    options: &DiffOptions,
+) -> BsDiffResult<D> {
+    let Trim {
+        prefix,
+        suffix,
+        window,
+    } = trim;
+    let old_middle = &old[window.0..window.1];
+    let new_middle = &new[prefix..new.len() - suffix];
+    let mut controls = vec![Control {
+        diff_len: prefix,
+        extra_len: 0,
+        seek: window.0 as i64 - prefix as i64,
+    }];
+    if !new_middle.is_empty() {
+        let index = OldIndex::new(old_middle);
+        let tree = index.suffix_index().search_tree();
+        controls.extend(match index.suffix_index().suffixes() {
+            Entries::Narrow(I) => scan_segments(old_middle, new_middle, I, tree, options)?,
+            Entries::Wide(I) => scan_segments(old_middle, new_middle, I, tree, options)?,
+        });
+    }
+    // Move to the start of the shared suffix, wherever the middle left off
+    let end: i64 = controls
+        .iter()
+        .map(|control| control.diff_len as i64 + control.seek)
+        .sum();
+    if let Some(last) = controls.last_mut() {
+        last.seek += (old.len() - suffix) as i64 - end;
+    }
+    if suffix > 0 {
+        controls.push(Control {
+            diff_len: suffix,
+            extra_len: 0,
+            seek: 0,
+        });
+    }
 
+    let mut writer = PatchWriter::new(old, new, req, x64);
+    for control in &controls {
+        if control.diff_len + control.extra_len > 0 || control.seek != 0 {
+            writer.write(control)?;
+        }
+    }
+    Ok(writer.req.data)
+}
 
-fn write_control(buffer: &mut Vec<u8>, mut value: i64, x64_bit: bool) -> std::io::Result<()> {
-    if x64_bit {
-        buffer.write_i64::<LittleEndian>(value)?;
+fn bsdiff_with_index<D, Idx: SaIndex>(
+    old: &[u8],
+    new: &[u8],
+    I: &[Idx],
+    tree: &SearchTree,
+    req: BsDiffRequest<D>,
+    x64: bool,
+    options: &DiffOptions,
+) -> BsDiffResult<D> {
+    let mut writer = PatchWriter::new(old, new, req, x64);
+    if new.len().div_ceil(options.segments.max(1)) >= new.len() {
+        bsdiff_scan(old, new, I, tree, options, |control| writer.write(&control))?;
     } else {
-        let neg = value < 0;
-        if value < 0 {
-            value *= -1;
+        for control in scan_segments(old, new, I, tree, options)? {
+            writer.write(&control)?;
         }
-        buffer.write_i32::<LittleEndian>(i32::try_from(value).unwrap())?;
-        buffer.write_u8(0)?;
-        buffer.write_u8(0)?;
-        buffer.write_u8(0)?;
-        buffer.write_u8(if neg { 0x80 } else { 0 })?;
     }
-
-    Ok(())
+    Ok(writer.req.data)
 }
 
-pub fn bsdiff_internal<D>(old: &[u8], new: &[u8], req: BsDiffRequest<D>, x64: bool) -> BsDiffResult<D> {
-    let BsDiffRequest {
-        mut data,
-        ctrl_stream,
-        diff_stream,
-        extra_stream,
-    } = req;
-    let V: &mut [isize] = &mut *vec![0isize; old.len() + 1];
-    let I: &mut [isize] = &mut *vec![0isize; old.len() + 1];
-
-    qsufsort(I, V, old);
-
-    let buffer: &mut [u8] = &mut *vec![0u8; new.len()];
+/// The control entries for `new` split into `options.segments` parts, which are matched
+/// independently.
+fn scan_segments<Idx: SaIndex>(
+    old: &[u8],
+    new: &[u8],
+    I: &[Idx],
+    tree: &SearchTree,
+    options: &DiffOptions,
+) -> BsDiffResult<Vec<Control>> {
+    let segment_len = new.len().div_ceil(options.segments.max(1)).max(1);
+    let ranges: Vec<(usize, usize)> = (0..new.len())
+        .step_by(segment_len)
+        .map(|start| (start, min(start + segment_len, new.len())))
+        .collect();
+    let scan_segment = |&(start, end): &(usize, usize)| {
+        let mut controls = Vec::new();
+        bsdiff_scan(old, &new[start..end], I, tree, options, |control| {
+            controls.push(control);
+            Ok(())
+        })
+        .map(|_| controls)
+    };
+    #[cfg(feature = "parallel")]
+    let scanned: BsDiffResult<Vec<Vec<Control>>> = {
+        use rayon::prelude::*;
+        ranges.par_iter().map(scan_segment).collect()
+    };
+    #[cfg(not(feature = "parallel"))]
+    let scanned: BsDiffResult<Vec<Vec<Control>>> = ranges.iter().map(scan_segment).collect();
+
+    // Every segment was diffed as if it were a whole file, so it expects to start reading old
+    // at 0. The last seek of each segment is redirected there.
+    let mut scanned = scanned?;
+    let last_segment = scanned.len().saturating_sub(1);
+    for controls in &mut scanned[..last_segment] {
+        let end: i64 = controls
+            .iter()
+            .map(|control| control.diff_len as i64 + control.seek)
+            .sum();
+        if let Some(last) = controls.last_mut() {
+            last.seek -= end;
+        }
+    }
+    Ok(scanned.into_iter().flatten().collect())
+}
 
+///
+/// Finds the control entries turning `old` into `new`, handing each to `emit` in order.
+///
+/// When a match is not good enough to switch alignment, the scan moves on one byte at a time.
+/// Searching afresh at each of those bytes walks the same long match again and again, which made
+/// long runs and repeated records quadratic. A match longer than `options.reuse_len` (64 by
+/// default) is instead shortened by one byte and reused, so a search only happens where the
+/// previous match was short or was just taken. A search compares at most `log2(old.len()) + 2`
+/// suffixes over at most one byte more than the match it returns, so searching costs at most
+/// `O(new.len() * reuse_len * log(old.len()))` byte comparisons in total.
+///
+fn bsdiff_scan<Idx: SaIndex, F: FnMut(Control) -> BsDiffResult<()>>(
+    old: &[u8],
+    new: &[u8],
+    I: &[Idx],
+    tree: &SearchTree,
+    options: &DiffOptions,
+    mut emit: F,
+) -> BsDiffResult<()> {
+    let kernel = Kernel::detect();
+    let penalty = options.mismatch_penalty;
     // Compute the differences, writing ctrl as we go
     let mut scan = 0;
     let mut len = 0;
@@ -257,19 +524,24 @@ pub fn bsdiff_internal<D>(old: &[u8], new: &[u8], req: BsDiffRequest<D>, x64: bo
         let mut oldscore = 0;
         scan += len;
         let mut scsc = scan;
+        let mut reuse = false;
         while scan < new.len() {
-            len = search(I, old, &new[scan..], 0, old.len(), &mut pos) as usize;
+            if reuse && len > options.reuse_len {
+                // The previous match, minus the byte just passed
+                len -= 1;
+                pos += 1;
+            } else {
+                len = search(tree, I, old, &new[scan..], &mut pos) as usize;
+            }
 
-            while scsc < scan + len {
-                if scsc as isize + lastoffset < old.len() as isize
-                    && old[(scsc as isize + lastoffset) as usize] == new[scsc]
-                {
-                    oldscore += 1
-                }
-                scsc += 1;
+            // Bytes of the match that the previous alignment already predicts
+            let oldstart = (scsc as isize + lastoffset) as usize;
+            if scsc < scan + len && oldstart < old.len() {
+                oldscore += kernel.count_equal(&old[oldstart..], &new[scsc..scan + len]);
             }
+            scsc = scsc.max(scan + len);
 
-            if len == oldscore && len != 0 || len > oldscore + 8 {
+            if len == oldscore && len != 0 || len > oldscore + options.min_gain {
                 break;
             }
             if scan as isize + lastoffset < old.len() as isize
@@ -279,100 +551,100 @@ pub fn bsdiff_internal<D>(old: &[u8], new: &[u8], req: BsDiffRequest<D>, x64: bo
             }
 
             scan += 1;
+            reuse = true;
         }
 
-        if len != oldscore || scan == new.len() {
-            let mut s = 0;
-            let mut Sf = 0;
-            let mut lenf = 0;
-            let mut i = 0;
-            while lastscan + i < scan && lastpos + i < old.len() {
-                if old[lastpos + i] == new[lastscan + i] {
-                    s += 1
-                }
-                i += 1;
-                if s as i64 * 2 - i as i64 > Sf as i64 * 2 - lenf as i64 {
-                    Sf = s;
-                    lenf = i;
+        let switch = len != oldscore || scan == new.len();
+        if switch && scan < new.len() && (options.candidates > 0 || options.lookahead > 0) {
+            let choose = |at: usize| {
+                let target = at as isize + lastoffset;
+                best_candidate(kernel, tree, I, old, new, at, options.candidates, target)
+            };
+            // How far into `new` a match starting at `at` is expected to be copied from old
+            let reach = |at: usize, (pos, len): (usize, usize)| {
+                let after = &new[at + len..min(at + len + CONTEXT_LEN, new.len())];
+                at + len + kernel.count_equal(&old[pos + len..], after)
+            };
+            let mut best = choose(scan);
+            let mut best_reach = reach(scan, best);
+            // A match starting a little later may reach further
+            for later in scan + 1..min(scan + 1 + options.lookahead, new.len()) {
+                let candidate = choose(later);
+                let later_reach = reach(later, candidate);
+                if later_reach > best_reach + options.min_gain
+                    && candidate.0 as isize - later as isize != lastoffset
+                {
+                    scan = later;
+                    best = candidate;
+                    best_reach = later_reach;
                 }
             }
+            pos = best.0 as isize;
+            len = best.1;
+        }
+
+        if switch {
+            let mut lenf = kernel.best_forward(&old[lastpos..], &new[lastscan..scan], penalty);
 
             let mut lenb = 0;
             if scan < new.len() {
-                let mut s = 0;
-                let mut Sb = 0;
-                let mut i = 1;
-                while scan >= lastscan + i && pos as usize >= i {
-                    if old[pos as usize - i] == new[scan - i] {
-                        s += 1
-                    }
-                    if (s as i64) * 2 - (i as i64) > (Sb as i64) * 2 - (lenb as i64) {
-                        Sb = s;
-                        lenb = i;
-                    }
-
-                    i += 1;
-                }
+                let n = min(scan - lastscan, pos as usize);
+                let oldstart = pos as usize - n;
+                lenb = kernel.best_backward(
+                    &old[oldstart..oldstart + n],
+                    &new[scan - n..scan],
+                    penalty,
+                );
             }
 
             if lastscan + lenf > scan - lenb {
                 let overlap = (lastscan + lenf) - (scan - lenb);
-                let mut s = 0;
-                let mut Ss = 0;
-                let mut lens = 0;
-
-                for i in 0..overlap {
-                    if new[lastscan + lenf - overlap + i] == old[lastpos + lenf - overlap + i] {
-                        s += 1
-                    }
-                    if new[scan - lenb + i] == old[pos as usize - lenb + i] {
-                        s -= 1
-                    }
-                    if s > Ss {
-                        Ss = s;
-                        lens = i + 1;
-                    }
-                }
-
-                lenf += lens - overlap;
+                let forward = lenf - overlap;
+                let backward = pos as usize - lenb;
+                let lens = kernel.best_overlap(
+                    &new[lastscan + forward..lastscan + lenf],
+                    &old[lastpos + forward..lastpos + lenf],
+                    &new[scan - lenb..scan - lenb + overlap],
+                    &old[backward..backward + overlap],
+                );
+
+                lenf = lenf + lens - overlap;
                 lenb -= lens;
             }
 
-            // Write Control Data
-            let mut ctrl_buffer = Vec::new();//[0u8; 3 * 8];
-            let mut ctrl_write: &mut [u8] = &mut ctrl_buffer;
-            write_control(&mut ctrl_buffer, lenf as i64, x64)?;
-            write_control(&mut ctrl_buffer, (scan - lenb) as i64 - (lastscan + lenf) as i64, x64)?;
-            write_control(&mut ctrl_buffer, (pos as i64 - lenb as i64) - (lastpos as i64 + lenf as i64), x64)?;
-
-            ctrl_stream(&mut data, &ctrl_buffer)?;
-
-            // Write Diff Data
-            for i in 0..lenf {
-                buffer[i] = new[lastscan + i].wrapping_sub(old[lastpos + i]);
-            }
-            diff_stream(&mut data, &buffer[..lenf])?;
-
-            // Write Extra Data
-            for i in 0..((scan - lenb) - (lastscan + lenf)) {
-                buffer[i] = new[lastscan + lenf + i];
-            }
-            extra_stream(&mut data, &buffer[..((scan - lenb) - (lastscan + lenf))])?;
+            emit(Control {
+                diff_len: lenf,
+                extra_len: (scan - lenb) - (lastscan + lenf),
+                seek: (pos as i64 - lenb as i64) - (lastpos as i64 + lenf as i64),
+            })?;
 
             if scan < new.len() {
                 lastscan = scan - lenb;
                 lastpos = pos as usize - lenb;
-                lastoffset = pos as isize - scan as isize;
+                lastoffset = pos - scan as isize;
             }
         }
     }
 
-    Ok(data)
+    Ok(())
+}
+
+pub fn bsdiff_raw_32bit<O: OldData + ?Sized, W: Write>(
+    old: &O,
+    new: &[u8],
+    patch: W,
+) -> BsDiffResult<()> {
+    bsdiff_raw_32bit_with(old, new, patch, &DiffOptions::default())
 }
 
-pub fn bsdiff_raw_32bit<W: Write>(old: &[u8], new: &[u8], patch: W) -> BsDiffResult<()> {
+pub fn bsdiff_raw_32bit_with<O: OldData + ?Sized, W: Write>(
+    old: &O,
+    new: &[u8],
+    patch: W,
+    options: &DiffOptions,
+) -> BsDiffResult<()> {
     let stream_fn: fn(&mut W, &[u8]) -> BsDiffResult<()> =
-        |patch: &mut W, buffer| patch.write_all(buffer);
+        |patch: &mut W, buffer| Ok(patch.write_all(buffer)?);
     let req = BsDiffRequest {
         data: patch,
         ctrl_stream: stream_fn,
@@ -380,14 +652,26 @@ pub fn bsdiff_raw_32bit<W: Write>(old: &[u8], new: &[u8], patch: W) -> BsDiffRes
         extra_stream: stream_fn,
     };
 
-    bsdiff_internal(old, new, req, false)?;
+    bsdiff_internal(old, new, req, false, options)?;
     Ok(())
 }
 
+pub fn bsdiff_raw<O: OldData + ?Sized, W: Write>(
+    old: &O,
+    new: &[u8],
+    patch: W,
+) -> BsDiffResult<()> {
+    bsdiff_raw_with(old, new, patch, &DiffOptions::default())
+}
 
-pub fn bsdiff_raw<W: Write>(old: &[u8], new: &[u8], patch: W) -> BsDiffResult<()> {
+pub fn bsdiff_raw_with<O: OldData + ?Sized, W: Write>(
+    old: &O,
+    new: &[u8],
+    patch: W,
+    options: &DiffOptions,
+) -> BsDiffResult<()> {
     let stream_fn: fn(&mut W, &[u8]) -> BsDiffResult<()> =
-        |patch: &mut W, buffer| patch.write_all(buffer);
+        |patch: &mut W, buffer| Ok(patch.write_all(buffer)?);
     let req = BsDiffRequest {
         data: patch,
         ctrl_stream: stream_fn,
@@ -395,6 +679,26 @@ pub fn bsdiff_raw<W: Write>(old: &[u8], new: &[u8], patch: W) -> BsDiffResult<()
         extra_stream: stream_fn,
     };
 
-    bsdiff_internal(old, new, req, true)?;
+    bsdiff_internal(old, new, req, true, options)?;
     Ok(())
 }
+
+///
+/// Like `bsdiff_raw`, but splits `new` into `segments` equally sized parts that are matched
+/// against `old` independently, concurrently with the `parallel` feature. The result is the same
+/// however many threads run, applies with `bspatch_raw`, and is usually slightly larger than
+/// with a single segment because matches cannot cross segment boundaries.
+/// Shorthand for `bsdiff_raw_with` with only `DiffOptions::segments` changed.
+///
+pub fn bsdiff_raw_segmented<O: OldData + ?Sized, W: Write>(
+    old: &O,
+    new: &[u8],
+    patch: W,
+    segments: usize,
+) -> BsDiffResult<()> {
+    let options = DiffOptions {
+        segments,
+        ..DiffOptions::default()
+    };
+    bsdiff_raw_with(old, new, patch, &options)
+}
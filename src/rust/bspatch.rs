@@ -1,4 +1,7 @@
-use crate::BsDiffResult;
+use super::simd::Kernel;
+use crate::{BsDiffError, BsDiffResult, PatchOptions};
+use std::cmp::min;
+use std::convert::TryFrom;
 use byteorder::{LittleEndian, ReadBytesExt};
 use std::io::{Read, Write};
 
@@ -9,12 +12,57 @@ pub struct BsPatchRequest<D> {
     pub extra_stream: fn(&mut D, &mut [u8]) -> BsDiffResult<()>,
 }
 
+/// Diff and extra runs are streamed through a buffer of this size, however long they are.
+const SCRATCH_LEN: usize = 64 * 1024;
+
+/// Converts a length from a control entry, which must not be negative.
+fn control_len(value: i64) -> BsDiffResult<usize> {
+    usize::try_from(value).map_err(|_| BsDiffError::InvalidControl)
+}
+
+/// Reads a control value written in 32-bit mode: the magnitude in the low four bytes, zeros,
+/// and the sign in the top bit.
+fn read_control_32bit(buffer: &mut &[u8]) -> BsDiffResult<i64> {
+    let magnitude = buffer.read_u32::<LittleEndian>()?;
+    let high = buffer.read_u32::<LittleEndian>()?;
+    if magnitude > i32::MAX as u32 || high & 0x7fff_ffff != 0 {
+        return Err(BsDiffError::InvalidControl);
+    }
+    Ok(if high >> 31 == 1 {
+        -(magnitude as i64)
+    } else {
+        magnitude as i64
+    })
+}
+
+/// Advances `pos` by `by`, failing if the result overflows or, in strict mode, leaves `0..=old_len`.
+fn seek(pos: i64, by: i64, old_len: usize, strict: bool) -> BsDiffResult<i64> {
+    let pos = pos.checked_add(by).ok_or(BsDiffError::InvalidControl)?;
+    if strict && (pos < 0 || pos as u64 > old_len as u64) {
+        return Err(BsDiffError::InvalidControl);
+    }
+    Ok(pos)
+}
+
+/// Adds the old bytes starting at `oldpos` to `buffer`. Old bytes outside of `old` count as zero.
+fn add_old(kernel: Kernel, buffer: &mut [u8], old: &[u8], oldpos: i64) {
+    // The part of the buffer that lines up with `old`
+    let start = oldpos.clamp(0, old.len() as i64);
+    let end = oldpos.saturating_add(buffer.len() as i64).clamp(start, old.len() as i64);
+    // Saturates for positions far before `old`, which leave nothing of the buffer lined up
+    let skip = start.saturating_sub(oldpos).clamp(0, buffer.len() as i64) as usize;
+    let len = (end - start) as usize;
+    kernel.add_bytes(&mut buffer[skip..skip + len], &old[start as usize..end as usize]);
+}
+
 pub fn bspatch_internal<D, W: Write>(
     old: &[u8],
     mut new: W,
     new_len: usize,
     req: BsPatchRequest<D>,
     x64: bool,
+    held: u64,
+    options: &PatchOptions,
 ) -> BsDiffResult<D> {
     let BsPatchRequest {
         mut data,
@@ -22,61 +70,96 @@ pub fn bspatch_internal<D, W: Write>(
         diff_stream,
         extra_stream,
     } = req;
-    let mut oldpos: usize = 0;
+    let mut oldpos: i64 = 0;
     let mut newpos: usize = 0;
     let mut ctrl_buff = [0u8; 3 * 8];
     let mut ctrl = [0i64; 3];
+    let kernel = Kernel::detect();
+    let limits = &options.limits;
+    limits.check_new_size(new_len as u64)?;
+    let scratch_len = min(SCRATCH_LEN, new_len);
+    // Counted together with whatever the caller holds for the streams already
+    limits.check_memory(held.saturating_add(scratch_len as u64))?;
+    let mut scratch = vec![0u8; scratch_len].into_boxed_slice();
 
     while newpos < new_len {
         ctrl_stream(&mut data, &mut ctrl_buff)?;
         let mut ctrl_buff_stream: &[u8] = &ctrl_buff;
         if x64 {
-            for i in 0..3 {
-                ctrl[i] = ctrl_buff_stream.read_i64::<LittleEndian>()?;
+            for value in ctrl.iter_mut() {
+                *value = ctrl_buff_stream.read_i64::<LittleEndian>()?;
             }
         } else {
-            for i in 0..3 {
-                ctrl[i] = ctrl_buff_stream.read_i32::<LittleEndian>()? as i64;
-                ctrl_buff_stream.read_i32::<LittleEndian>()?; // zero
+            for value in ctrl.iter_mut() {
+                *value = read_control_32bit(&mut ctrl_buff_stream)?;
             }
         }
 
-        if newpos + ctrl[0] as usize > new_len {
-            return Err(invalid_data!());
+        let diff_len = control_len(ctrl[0])?;
+        let extra_len = control_len(ctrl[1])?;
+        limits.check_op_len(diff_len as u64)?;
+        limits.check_op_len(extra_len as u64)?;
+
+        let diff_end = newpos
+            .checked_add(diff_len)
+            .ok_or(BsDiffError::InvalidControl)?;
+        if diff_end > new_len {
+            return Err(BsDiffError::OutputLengthMismatch {
+                expected: new_len,
+                actual: diff_end,
+            });
         }
-
-        let mut buffer = vec![0u8; ctrl[0] as usize].into_boxed_slice();
-
-        diff_stream(&mut data, &mut buffer)?;
-
-        for i in 0..(ctrl[0] as usize) {
-            if oldpos + i < old.len() {
-                buffer[i] = buffer[i].overflowing_add(old[oldpos + i]).0;
-            }
+        let mut chunk_oldpos = oldpos;
+        oldpos = seek(oldpos, ctrl[0], old.len(), options.strict)?;
+
+        let mut remaining = diff_len;
+        while remaining > 0 {
+            let chunk = &mut scratch[..min(remaining, scratch_len)];
+            diff_stream(&mut data, chunk)?;
+            add_old(kernel, chunk, old, chunk_oldpos);
+            new.write_all(chunk)?;
+            chunk_oldpos += chunk.len() as i64;
+            remaining -= chunk.len();
         }
 
-        new.write_all(&mut buffer)?;
+        newpos = diff_end;
 
-        newpos = (newpos as i64 + ctrl[0]) as usize;
-        oldpos = (oldpos as i64 + ctrl[0]) as usize;
-
-        if newpos + ctrl[1] as usize > new_len {
-            return Err(invalid_data!());
+        let extra_end = newpos
+            .checked_add(extra_len)
+            .ok_or(BsDiffError::InvalidControl)?;
+        if extra_end > new_len {
+            return Err(BsDiffError::OutputLengthMismatch {
+                expected: new_len,
+                actual: extra_end,
+            });
+        }
+        let mut remaining = extra_len;
+        while remaining > 0 {
+            let chunk = &mut scratch[..min(remaining, scratch_len)];
+            extra_stream(&mut data, chunk)?;
+            new.write_all(chunk)?;
+            remaining -= chunk.len();
         }
-        let mut buffer = vec![0u8; ctrl[1] as usize];
-        extra_stream(&mut data, &mut buffer)?;
-        new.write_all(&mut buffer)?;
 
-        newpos = (newpos as i64 + ctrl[1]) as usize;
-        oldpos = (oldpos as i64 + ctrl[2]) as usize;
+        newpos = extra_end;
+        oldpos = seek(oldpos, ctrl[2], old.len(), options.strict)?;
     }
 
     Ok(data)
 }
 
 pub fn bspatch_raw_32bit<R: Read>(old: &[u8], new: &mut [u8], patch: R) -> BsDiffResult<()> {
+    bspatch_raw_32bit_with(old, new, patch, &PatchOptions::default())
+}
+
+pub fn bspatch_raw_32bit_with<R: Read>(
+    old: &[u8],
+    new: &mut [u8],
+    patch: R,
+    options: &PatchOptions,
+) -> BsDiffResult<()> {
     let stream_fn: fn(&mut R, &mut [u8]) -> BsDiffResult<()> =
-    |patch, buffer| patch.read_exact(buffer);
+    |patch, buffer| Ok(patch.read_exact(buffer)?);
     let req = BsPatchRequest {
         data: patch,
         ctrl_stream: stream_fn,
@@ -86,14 +169,22 @@ pub fn bspatch_raw_32bit<R: Read>(old: &[u8], new: &mut [u8], patch: R) -> BsDif
 
     let new_len = new.len();
 
-    bspatch_internal(old, new, new_len, req, false)?;
+    bspatch_internal(old, new, new_len, req, false, 0, options)?;
     Ok(())
 }
 
-
 pub fn bspatch_raw<R: Read>(old: &[u8], new: &mut [u8], patch: R) -> BsDiffResult<()> {
+    bspatch_raw_with(old, new, patch, &PatchOptions::default())
+}
+
+pub fn bspatch_raw_with<R: Read>(
+    old: &[u8],
+    new: &mut [u8],
+    patch: R,
+    options: &PatchOptions,
+) -> BsDiffResult<()> {
     let stream_fn: fn(&mut R, &mut [u8]) -> BsDiffResult<()> =
-        |patch, buffer| patch.read_exact(buffer);
+        |patch, buffer| Ok(patch.read_exact(buffer)?);
     let req = BsPatchRequest {
         data: patch,
         ctrl_stream: stream_fn,
@@ -103,6 +194,6 @@ pub fn bspatch_raw<R: Read>(old: &[u8], new: &mut [u8], patch: R) -> BsDiffResul
 
     let new_len = new.len();
 
-    bspatch_internal(old, new, new_len, req, true)?;
+    bspatch_internal(old, new, new_len, req, true, 0, options)?;
     Ok(())
 }
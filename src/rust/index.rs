@@ -0,0 +1,257 @@
+//!
+//! A reusable suffix array over the old data.
+//!
+//! Building the suffix array is the most expensive part of a diff, and it only depends on the old
+//! data. Diffing many new versions against the same base can build it once with `OldIndex::new`,
+//! or once per build machine with `OldIndex::write_to` and `OldIndex::read_from`.
+//!
+//! The file format is a 28 byte header, the entries, and a CRC-32 of everything before it:
+//!
+//! | Offset | Size | Content |
+//! |--------|------|---------|
+//! | 0 | 8 | `BSDIFFIX` |
+//! | 8 | 4 | format version, currently 1 |
+//! | 12 | 4 | bytes per entry, 4 or 8 |
+//! | 16 | 8 | length of the old data |
+//! | 24 | 4 | CRC-32 of the old data |
+//!
+//! All integers are little endian.
+//!
+
+use super::sais::SaIndex;
+use super::suffix_index::{Entries, SuffixIndex};
+use crate::{BsDiffError, BsDiffResult};
+use byteorder::{ByteOrder, LittleEndian, ReadBytesExt, WriteBytesExt};
+use crc32fast::Hasher;
+use std::cmp::min;
+use std::io::{self, Read, Write};
+
+const MAGIC_NUMBER_INDEX: &[u8] = b"BSDIFFIX";
+const INDEX_VERSION: u32 = 1;
+
+/// Entries are converted to and from bytes this many at a time
+const CHUNK_ENTRIES: usize = 8 * 1024;
+
+///
+/// The suffix array of some old data, built once and reused for any number of diffs.
+/// Pass it to `bsdiff_raw`, `bsdiff43` or `jbsdiff40` wherever the old data is expected.
+///
+pub struct OldIndex<'a> {
+    index: SuffixIndex<'a>,
+}
+
+impl<'a> OldIndex<'a> {
+    /// Builds the index of `old`.
+    pub fn new(old: &'a [u8]) -> Self {
+        OldIndex {
+            index: SuffixIndex::new(old),
+        }
+    }
+
+    /// The old data this index was built from
+    pub fn old(&self) -> &'a [u8] {
+        self.index.text()
+    }
+
+    /// Substring queries over the old data
+    pub fn suffix_index(&self) -> &SuffixIndex<'a> {
+        &self.index
+    }
+
+    /// Saves the index. Only the suffix array is written, not the old data.
+    pub fn write_to<W: Write>(&self, mut writer: W) -> BsDiffResult<()> {
+        let mut header = Vec::with_capacity(28);
+        header.write_all(MAGIC_NUMBER_INDEX)?;
+        header.write_u32::<LittleEndian>(INDEX_VERSION)?;
+        header.write_u32::<LittleEndian>(match self.index.suffixes() {
+            Entries::Narrow(_) => 4,
+            Entries::Wide(_) => 8,
+        })?;
+        header.write_u64::<LittleEndian>(self.old().len() as u64)?;
+        header.write_u32::<LittleEndian>(crc32fast::hash(self.old()))?;
+
+        let mut hasher = Hasher::new();
+        hasher.update(&header);
+        writer.write_all(&header)?;
+        match self.index.suffixes() {
+            Entries::Narrow(entries) => write_entries(entries, &mut writer, &mut hasher)?,
+            Entries::Wide(entries) => write_entries(entries, &mut writer, &mut hasher)?,
+        }
+        writer.write_u32::<LittleEndian>(hasher.finalize())?;
+        Ok(())
+    }
+
+    ///
+    /// Loads an index saved by `write_to`. `old` must be the data it was built from,
+    /// which is checked against the length and checksum in the header.
+    ///
+    pub fn read_from<R: Read>(old: &'a [u8], mut reader: R) -> BsDiffResult<Self> {
+        let mut header = [0u8; 28];
+        reader
+            .read_exact(&mut header)
+            .map_err(BsDiffError::from_header)?;
+        if &header[..8] != MAGIC_NUMBER_INDEX {
+            return Err(BsDiffError::BadMagic);
+        }
+        let mut header_iter = &header[8..];
+        let version = header_iter.read_u32::<LittleEndian>()?;
+        let width = header_iter.read_u32::<LittleEndian>()?;
+        let old_len = header_iter.read_u64::<LittleEndian>()?;
+        let old_crc = header_iter.read_u32::<LittleEndian>()?;
+        if version != INDEX_VERSION {
+            return Err(BsDiffError::CorruptIndex);
+        }
+        if old_len != old.len() as u64 || old_crc != crc32fast::hash(old) {
+            return Err(BsDiffError::IndexMismatch);
+        }
+
+        let mut hasher = Hasher::new();
+        hasher.update(&header);
+        let suffixes = match width {
+            4 if old.len() < u32::MAX as usize => {
+                Entries::Narrow(read_entries(old.len(), &mut reader, &mut hasher)?)
+            }
+            8 => Entries::Wide(read_entries(old.len(), &mut reader, &mut hasher)?),
+            _ => return Err(BsDiffError::CorruptIndex),
+        };
+        let crc = reader.read_u32::<LittleEndian>().map_err(index_error)?;
+        if crc != hasher.finalize() {
+            return Err(BsDiffError::CorruptIndex);
+        }
+        Ok(OldIndex {
+            index: SuffixIndex::from_suffixes(old, suffixes),
+        })
+    }
+}
+
+///
+/// Old data to diff against: either the bytes themselves, or an `OldIndex` built from them.
+///
+pub trait OldData {
+    fn old_bytes(&self) -> &[u8];
+
+    /// A prebuilt index of `old_bytes`, if there is one
+    fn old_index(&self) -> Option<&OldIndex<'_>> {
+        None
+    }
+}
+
+impl OldData for [u8] {
+    fn old_bytes(&self) -> &[u8] {
+        self
+    }
+}
+
+impl OldData for Vec<u8> {
+    fn old_bytes(&self) -> &[u8] {
+        self
+    }
+}
+
+impl<const N: usize> OldData for [u8; N] {
+    fn old_bytes(&self) -> &[u8] {
+        self
+    }
+}
+
+impl<'a> OldData for OldIndex<'a> {
+    fn old_bytes(&self) -> &[u8] {
+        self.old()
+    }
+
+    fn old_index(&self) -> Option<&OldIndex<'_>> {
+        Some(self)
+    }
+}
+
+impl<T: OldData + ?Sized> OldData for &T {
+    fn old_bytes(&self) -> &[u8] {
+        (**self).old_bytes()
+    }
+
+    fn old_index(&self) -> Option<&OldIndex<'_>> {
+        (**self).old_index()
+    }
+}
+
+/// Runs `f` with the index of `old`, building one if it does not have one already.
+pub(crate) fn with_index<O: OldData + ?Sized, T>(old: &O, f: impl FnOnce(&OldIndex) -> T) -> T {
+    match old.old_index() {
+        Some(index) => f(index),
+        None => f(&OldIndex::new(old.old_bytes())),
+    }
+}
+
+/// A premature end of the entries means the file is damaged rather than the header
+fn index_error(err: io::Error) -> BsDiffError {
+    match err.kind() {
+        io::ErrorKind::UnexpectedEof => BsDiffError::CorruptIndex,
+        _ => BsDiffError::Io(err),
+    }
+}
+
+/// Suffix array entries as stored on disk
+trait IndexEntry: SaIndex {
+    const WIDTH: usize;
+    fn write_into(src: &[Self], dst: &mut [u8]);
+    fn read_into(src: &[u8], dst: &mut [Self]);
+}
+
+impl IndexEntry for u32 {
+    const WIDTH: usize = 4;
+
+    fn write_into(src: &[Self], dst: &mut [u8]) {
+        LittleEndian::write_u32_into(src, dst)
+    }
+
+    fn read_into(src: &[u8], dst: &mut [Self]) {
+        LittleEndian::read_u32_into(src, dst)
+    }
+}
+
+impl IndexEntry for u64 {
+    const WIDTH: usize = 8;
+
+    fn write_into(src: &[Self], dst: &mut [u8]) {
+        LittleEndian::write_u64_into(src, dst)
+    }
+
+    fn read_into(src: &[u8], dst: &mut [Self]) {
+        LittleEndian::read_u64_into(src, dst)
+    }
+}
+
+fn write_entries<Idx: IndexEntry, W: Write>(
+    entries: &[Idx],
+    writer: &mut W,
+    hasher: &mut Hasher,
+) -> BsDiffResult<()> {
+    let mut bytes = vec![0u8; min(entries.len(), CHUNK_ENTRIES) * Idx::WIDTH];
+    for chunk in entries.chunks(CHUNK_ENTRIES) {
+        let bytes = &mut bytes[..chunk.len() * Idx::WIDTH];
+        Idx::write_into(chunk, bytes);
+        hasher.update(bytes);
+        writer.write_all(bytes)?;
+    }
+    Ok(())
+}
+
+/// Reads the `old_len + 1` entries of an index, rejecting any that point past the old data.
+fn read_entries<Idx: IndexEntry, R: Read>(
+    old_len: usize,
+    reader: &mut R,
+    hasher: &mut Hasher,
+) -> BsDiffResult<Vec<Idx>> {
+    let mut entries = vec![Idx::EMPTY; old_len + 1];
+    let mut bytes = vec![0u8; min(entries.len(), CHUNK_ENTRIES) * Idx::WIDTH];
+    for chunk in entries.chunks_mut(CHUNK_ENTRIES) {
+        let bytes = &mut bytes[..chunk.len() * Idx::WIDTH];
+        reader.read_exact(bytes).map_err(index_error)?;
+        hasher.update(bytes);
+        Idx::read_into(bytes, chunk);
+        if chunk.iter().any(|entry| entry.to_usize() > old_len) {
+            return Err(BsDiffError::CorruptIndex);
+        }
+    }
+    Ok(entries)
+}
@@ -1,17 +1,114 @@
-macro_rules! invalid_data {
-    () => {
-        std::io::Error::new(
-            std::io::ErrorKind::InvalidData,
-            "Patch Instructions Invalid",
-        )
-    };
-}
+use crate::{Backend, BsDiffError, BsDiffResult, DiffOptions, PatchOptions};
+use std::io::{Read, Write};
 
 mod bsdiff;
-pub use bsdiff::bsdiff_internal;
+mod index;
+pub use index::{OldData, OldIndex};
+#[cfg(feature = "parallel")]
+mod par_sufsort;
+#[cfg(feature = "bench_internals")]
+mod qsufsort;
+mod sais;
+#[doc(hidden)]
+pub mod simd;
+mod suffix_index;
+pub use suffix_index::SuffixIndex;
+pub(crate) use bsdiff::bsdiff_internal;
+pub(crate) use bsdiff::trims;
 pub use bsdiff::bsdiff_raw;
-pub use bsdiff::BsDiffRequest;
+pub use bsdiff::bsdiff_raw_32bit;
+pub use bsdiff::bsdiff_raw_32bit_with;
+pub use bsdiff::bsdiff_raw_segmented;
+pub use bsdiff::bsdiff_raw_with;
+pub(crate) use bsdiff::BsDiffRequest;
 mod bspatch;
-pub use bspatch::bspatch_internal;
+pub(crate) use bspatch::bspatch_internal;
 pub use bspatch::bspatch_raw;
-pub use bspatch::BsPatchRequest;
+pub use bspatch::bspatch_raw_32bit;
+pub use bspatch::bspatch_raw_32bit_with;
+pub use bspatch::bspatch_raw_with;
+pub(crate) use bspatch::BsPatchRequest;
+
+///
+/// Suffix sorts for benches/suffix_sort.rs and tests/suffix_test.rs, not part of the API.
+///
+#[cfg(feature = "bench_internals")]
+#[doc(hidden)]
+pub mod bench {
+    pub use super::qsufsort::qsufsort;
+
+    pub fn sais(old: &[u8]) -> Vec<u32> {
+        super::sais::sais(old)
+    }
+
+    pub fn sais_64(old: &[u8]) -> Vec<u64> {
+        super::sais::sais(old)
+    }
+
+    #[cfg(feature = "parallel")]
+    pub fn par_sufsort(old: &[u8]) -> Vec<u32> {
+        super::par_sufsort::par_sufsort(old)
+    }
+}
+
+///
+/// The pure Rust implementation of bsdiff.
+///
+pub struct RustBackend;
+
+impl Backend for RustBackend {
+    #[inline]
+    fn bsdiff_raw<W: Write>(old: &[u8], new: &[u8], patch: W) -> BsDiffResult<()> {
+        bsdiff_raw(old, new, patch)
+    }
+
+    #[inline]
+    fn bsdiff_raw_with<W: Write>(
+        old: &[u8],
+        new: &[u8],
+        patch: W,
+        options: &DiffOptions,
+    ) -> BsDiffResult<()> {
+        bsdiff_raw_with(old, new, patch, options)
+    }
+
+    #[inline]
+    fn bsdiff_raw_indexed<W: Write>(
+        old: &OldIndex,
+        new: &[u8],
+        patch: W,
+        options: &DiffOptions,
+    ) -> BsDiffResult<()> {
+        bsdiff_raw_with(old, new, patch, options)
+    }
+
+    #[inline]
+    fn bspatch_raw_with<R: Read>(
+        old: &[u8],
+        new: &mut [u8],
+        patch: R,
+        options: &PatchOptions,
+    ) -> BsDiffResult<()> {
+        bspatch_raw_with(old, new, patch, options)
+    }
+
+    fn bspatch_stream<W: Write, R: Read>(
+        old: &[u8],
+        new: W,
+        new_len: usize,
+        patch: R,
+        options: &PatchOptions,
+    ) -> BsDiffResult<()> {
+        // Rust Backend can avoid a copy by using bspatch_internal
+        let read_fun: fn(&mut R, &mut [u8]) -> BsDiffResult<()> =
+            |data, buffer| data.read_exact(buffer).map_err(BsDiffError::from_compressed);
+        let req = BsPatchRequest {
+            data: patch,
+            ctrl_stream: read_fun,
+            diff_stream: read_fun,
+            extra_stream: read_fun,
+        };
+        bspatch_internal(old, new, new_len, req, true, 0, options)?;
+        Ok(())
+    }
+}
@@ -0,0 +1,136 @@
+//!
+//! Suffix sorting by prefix doubling on the rayon thread pool.
+//!
+//! Suffixes are first grouped by their leading bytes. Every group still holding more than one
+//! suffix is then split by the rank of the suffix `h` bytes further on, doubling `h` each round
+//! (Larsson and Sadakane). Groups are independent of each other within a round, so they are
+//! sorted concurrently, and ranks are only updated once the whole round is done. A suffix array
+//! has exactly one correct order, so the result is identical to `sais`.
+//!
+//! The work is proportional to the sum of the logarithms of the longest repeat starting at each
+//! position, which is close to linear for typical files but not for periodic ones. When a large
+//! part of the text still shares long prefixes after a few rounds, the serial `sais` takes over.
+//! Memory use is about four times that of `sais`.
+//!
+
+use super::sais::{sais, SaIndex};
+use rayon::prelude::*;
+use std::mem;
+
+/// Bytes compared by the initial grouping, as many as fit a `u64` key
+const PREFIX_LEN: usize = 7;
+
+/// Groups smaller than this are sorted on a single thread
+const PAR_SORT_THRESHOLD: usize = 1 << 14;
+
+/// Once suffixes share prefixes this long, a text is considered periodic
+const PERIODIC_PREFIX_LEN: usize = 16;
+
+/// The first `PREFIX_LEN` bytes of the suffix at `i`, in base 257 so that the end of the text
+/// sorts before every byte
+#[inline]
+fn prefix_key(old: &[u8], i: usize) -> u64 {
+    let mut key = 0;
+    for j in i..i + PREFIX_LEN {
+        key = key * 257 + old.get(j).map_or(0, |&b| b as u64 + 1);
+    }
+    key
+}
+
+///
+/// Builds the suffix array of `old` using every thread of the current rayon pool.
+/// The result is the same as `sais::<Idx>(old)`.
+///
+pub(crate) fn par_sufsort<Idx: SaIndex>(old: &[u8]) -> Vec<Idx> {
+    let n = old.len();
+    let mut keyed: Vec<(u64, Idx)> = (0..=n)
+        .into_par_iter()
+        .map(|i| (prefix_key(old, i), Idx::from_usize(i)))
+        .collect();
+    keyed.par_sort_unstable();
+
+    // rank[i] is the position in `sa` of the last suffix in the group of suffix i
+    let mut rank = vec![Idx::from_usize(0); n + 1];
+    let mut groups = Vec::new();
+    let mut start = 0;
+    for k in 1..=n + 1 {
+        if k > n || keyed[k].0 != keyed[start].0 {
+            for &(_, i) in &keyed[start..k] {
+                rank[i.to_usize()] = Idx::from_usize(k - 1);
+            }
+            if k - start > 1 {
+                groups.push((start, k));
+            }
+            start = k;
+        }
+    }
+    let mut sa: Vec<Idx> = keyed.into_par_iter().map(|(_, i)| i).collect();
+
+    // new_rank is indexed like `sa`, so each group owns a disjoint part of it during a round
+    let mut new_rank = vec![Idx::from_usize(0); n + 1];
+    let mut h = PREFIX_LEN;
+    while !groups.is_empty() {
+        // Doubling needs a round per doubling of the longest repeat, on periodic text that
+        // is slower than the linear serial sort
+        let unsorted: usize = groups.iter().map(|&(start, end)| end - start).sum();
+        if h >= PERIODIC_PREFIX_LEN && unsorted > n / 4 {
+            return sais(old);
+        }
+
+        let mut parts = Vec::with_capacity(groups.len());
+        {
+            let mut sa_rest = &mut sa[..];
+            let mut rank_rest = &mut new_rank[..];
+            let mut offset = 0;
+            for &(start, end) in &groups {
+                let (_, sa_tail) = mem::take(&mut sa_rest).split_at_mut(start - offset);
+                let (sa_group, sa_tail) = sa_tail.split_at_mut(end - start);
+                let (_, rank_tail) = mem::take(&mut rank_rest).split_at_mut(start - offset);
+                let (rank_group, rank_tail) = rank_tail.split_at_mut(end - start);
+                parts.push((start, sa_group, rank_group));
+                sa_rest = sa_tail;
+                rank_rest = rank_tail;
+                offset = end;
+            }
+        }
+
+        // Suffixes in a group agree on their first h bytes and cannot end within them, so i + h <= n
+        let rank_ref = &rank;
+        let split: Vec<Vec<(usize, usize)>> = parts
+            .into_par_iter()
+            .map(|(start, sa_group, rank_group)| {
+                let key = |i: &Idx| rank_ref[i.to_usize() + h];
+                if sa_group.len() < PAR_SORT_THRESHOLD {
+                    sa_group.sort_unstable_by_key(key);
+                } else {
+                    sa_group.par_sort_unstable_by_key(key);
+                }
+
+                let mut unsorted = Vec::new();
+                let mut run = 0;
+                for k in 1..=sa_group.len() {
+                    if k == sa_group.len() || key(&sa_group[k]) != key(&sa_group[run]) {
+                        for r in &mut rank_group[run..k] {
+                            *r = Idx::from_usize(start + k - 1);
+                        }
+                        if k - run > 1 {
+                            unsorted.push((start + run, start + k));
+                        }
+                        run = k;
+                    }
+                }
+                unsorted
+            })
+            .collect();
+
+        for &(start, end) in &groups {
+            for k in start..end {
+                rank[sa[k].to_usize()] = new_rank[k];
+            }
+        }
+        groups = split.concat();
+        h *= 2;
+    }
+
+    sa
+}
@@ -0,0 +1,178 @@
+#![allow(non_snake_case)]
+
+use std::cmp::Ordering;
+
+fn split(I: &mut [isize], V: &mut [isize], start: isize, len: isize, h: isize) {
+    if len < 16 {
+        let mut k = start;
+        while k < start + len {
+            let mut j = 1;
+            let mut x = V[(I[k as usize] + h) as usize];
+            let mut i = 1;
+            while k + i < start + len {
+                let value = &V[(I[(k + i) as usize] + h) as usize];
+                if *value < x {
+                    x = *value;
+                    j = 0;
+                }
+                if *value == x {
+                    I.swap((k + j) as usize, (k + i) as usize);
+                    j += 1;
+                }
+                i += 1;
+            }
+
+            for i in 0..j {
+                V[(I[(k + i) as usize]) as usize] = k + j - 1;
+                if j == 1 {
+                    I[k as usize] = -1;
+                }
+            }
+            k += j;
+        }
+        return;
+    }
+
+    let x = V[(I[(start + len / 2) as usize] + h) as usize];
+    let mut jj = 0;
+    let mut kk = 0;
+    for i in start..(start + len) {
+        match V[(I[i as usize] + h) as usize].cmp(&x) {
+            Ordering::Less => jj += 1,
+            Ordering::Equal => kk += 1,
+            Ordering::Greater => {}
+        }
+    }
+    jj += start;
+    kk += jj;
+
+    let mut i = start;
+    let mut j = 0;
+    let mut k = 0;
+    while i < jj {
+        match V[(I[i as usize] + h) as usize].cmp(&x) {
+            Ordering::Less => i += 1,
+            Ordering::Equal => {
+                I.swap(i as usize, (j + jj) as usize);
+                j += 1;
+            }
+            Ordering::Greater => {
+                I.swap(i as usize, (k + kk) as usize);
+                k += 1;
+            }
+        }
+    }
+
+    while jj + j < kk {
+        if V[(I[(jj + j) as usize] + h) as usize] == x {
+            j += 1;
+        } else {
+            I.swap((jj + j) as usize, (kk + k) as usize);
+            k += 1;
+        }
+    }
+
+    if jj > start {
+        split(I, V, start, jj - start, h)
+    }
+    for i in 0..(kk - jj) {
+        V[I[(jj + i) as usize] as usize] = kk - 1
+    }
+
+    if jj == kk - 1 {
+        I[jj as usize] = -1
+    }
+    if start + len > kk {
+        split(I, V, kk, start + len - kk, h)
+    }
+}
+
+fn qsufsort_into(I: &mut [isize], V: &mut [isize], old: &[u8]) {
+    let buckets: &mut [isize] = &mut [0; 256];
+
+    // each index n is the frequency that the u8 value n occurs in old
+    for i in 0..old.len() {
+        buckets[old[i] as usize] += 1
+    }
+
+    // adds the previous index
+    // index n is the cumulative frequency of the u8 value n and all smaller values
+    // assert_eq!(buckets[255], old.len() as i64);
+    for i in 1..256 {
+        buckets[i] += buckets[i - 1]
+    }
+
+    // right shift 1 element, buckets[0] becomes 0
+    for i in (1..256).rev() {
+        buckets[i] = buckets[i - 1]
+    }
+    buckets[0] = 0;
+
+    // first step:
+    // buckets[n] is now cumulative frequency again because right shift + adding frequency of n
+    // means buckets[n] = cumulative of n - 1 + frequency of n => cumulative function again
+    //
+    // second step:
+    // I[n] is the index of the old array which contains the byte with the cumulative frequency of n
+    // in other words, `for x in 1..=old.len() {old[I[x]]}` returns old's elements in sorted order
+    // when n the sorted index, I[n + 1] is the index of old
+    for i in 0..old.len() {
+        buckets[old[i] as usize] += 1;
+        I[buckets[old[i] as usize] as usize] = i as isize;
+    }
+    I[0] = old.len() as isize;
+
+    // V[i] is the inverse of I[i], when i is old's index, V[i] the sorted index
+    for i in 0..old.len() {
+        V[i] = buckets[old[i] as usize]
+    }
+    V[old.len()] = 0;
+
+    // if I[n] points to a unique byte value of old, now it points to -1
+    for i in 1..256 {
+        if buckets[i] == buckets[i - 1] + 1 {
+            I[buckets[i] as usize] = -1
+        }
+    }
+    I[0] = -1;
+
+    let mut h = 1;
+    while I[0] != -(old.len() as isize + 1) {
+        let mut len: usize = 0;
+        let mut i: usize = 0;
+        while i <= old.len() {
+            if I[i] < 0 {
+                len += (-I[i]) as usize;
+                i += (-I[i]) as usize;
+            } else {
+                if len != 0 {
+                    I[i - len] = -(len as isize);
+                }
+                len = V[I[i] as usize] as usize + 1 - i;
+                split(I, V, i as isize, len as isize, h);
+                i += len;
+                len = 0;
+            }
+        }
+        if len != 0 {
+            I[i - len] = -(len as isize);
+        }
+
+        h += h;
+    }
+
+    for i in 0..=old.len() {
+        I[V[i] as usize] = i as isize
+    }
+}
+
+///
+/// Builds the suffix array of `old` with the Larsson-Sadakane algorithm used by the original bsdiff.
+/// The result has `old.len() + 1` entries, the first being the empty suffix.
+///
+pub fn qsufsort(old: &[u8]) -> Vec<isize> {
+    let mut V = vec![0isize; old.len() + 1];
+    let mut I = vec![0isize; old.len() + 1];
+    qsufsort_into(&mut I, &mut V, old);
+    I
+}
@@ -0,0 +1,324 @@
+//!
+//! Linear time suffix array construction by induced sorting (SA-IS).
+//!
+//! Nong, Zhang and Chan, "Two Efficient Algorithms for Linear Time Suffix Array Construction".
+//! No sentinel character is needed, and apart from the bucket arrays all work happens inside
+//! the output array, so building the index takes little more than the index itself.
+//!
+
+/// Below this length a comparison sort is faster than setting up the induced sort.
+const NAIVE_THRESHOLD: usize = 10;
+
+///
+/// An integer type suffix array entries are stored as.
+/// `u32` covers inputs shorter than 4 GiB with half the memory of `u64`.
+///
+pub(crate) trait SaIndex: Symbol + Send + Sync + 'static {
+    /// Marks an unused slot during construction; never a valid index
+    const EMPTY: Self;
+    fn from_usize(value: usize) -> Self;
+    fn to_usize(self) -> usize;
+}
+
+impl SaIndex for u32 {
+    const EMPTY: Self = u32::MAX;
+
+    #[inline]
+    fn from_usize(value: usize) -> Self {
+        value as u32
+    }
+
+    #[inline]
+    fn to_usize(self) -> usize {
+        self as usize
+    }
+}
+
+impl SaIndex for u64 {
+    const EMPTY: Self = u64::MAX;
+
+    #[inline]
+    fn from_usize(value: usize) -> Self {
+        value as u64
+    }
+
+    #[inline]
+    fn to_usize(self) -> usize {
+        self as usize
+    }
+}
+
+///
+/// A character of the text being sorted.
+/// The top level text is bytes, recursion levels sort names stored as `SaIndex`.
+///
+pub(crate) trait Symbol: sealed::Sealed + Copy + Ord {
+    fn rank(self) -> usize;
+}
+
+mod sealed {
+    /// Keeps `Symbol` and `SaIndex` to the widths below
+    pub trait Sealed {}
+    impl Sealed for u8 {}
+    impl Sealed for u32 {}
+    impl Sealed for u64 {}
+}
+
+impl Symbol for u8 {
+    #[inline]
+    fn rank(self) -> usize {
+        self as usize
+    }
+}
+
+impl Symbol for u32 {
+    #[inline]
+    fn rank(self) -> usize {
+        self as usize
+    }
+}
+
+impl Symbol for u64 {
+    #[inline]
+    fn rank(self) -> usize {
+        self as usize
+    }
+}
+
+///
+/// Builds the suffix array of `old`.
+/// The result has `old.len() + 1` entries, the first being the empty suffix, exactly like bsdiff's `qsufsort`.
+/// `Idx` must be able to hold `old.len() + 1` distinct values besides `SaIndex::EMPTY`.
+///
+pub(crate) fn sais<Idx: SaIndex>(old: &[u8]) -> Vec<Idx> {
+    let mut suffixes = vec![Idx::EMPTY; old.len() + 1];
+    suffixes[0] = Idx::from_usize(old.len());
+    sa_is(old, u8::MAX as usize, &mut suffixes[1..]);
+    suffixes
+}
+
+/// One bit per suffix, set when the suffix is S-type (smaller than the suffix after it).
+struct SuffixTypes {
+    bits: Vec<u64>,
+}
+
+impl SuffixTypes {
+    fn new<T: Symbol>(s: &[T]) -> SuffixTypes {
+        let n = s.len();
+        let mut types = SuffixTypes {
+            bits: vec![0; n.div_ceil(64)],
+        };
+        // The last suffix is L-type, as it is larger than the empty suffix
+        let mut next_s = false;
+        for i in (0..n.saturating_sub(1)).rev() {
+            let is_s = s[i] < s[i + 1] || (s[i] == s[i + 1] && next_s);
+            if is_s {
+                types.bits[i / 64] |= 1 << (i % 64);
+            }
+            next_s = is_s;
+        }
+        types
+    }
+
+    #[inline]
+    fn is_s(&self, i: usize) -> bool {
+        self.bits[i / 64] & (1 << (i % 64)) != 0
+    }
+
+    /// Whether `i` is a leftmost S-type position
+    #[inline]
+    fn is_lms(&self, i: usize) -> bool {
+        i > 0 && self.is_s(i) && !self.is_s(i - 1)
+    }
+
+    /// The next leftmost S-type position after `i`, or `n` if there is none
+    fn next_lms(&self, i: usize, n: usize) -> usize {
+        let mut j = i + 1;
+        while j < n && !self.is_lms(j) {
+            j += 1;
+        }
+        j
+    }
+}
+
+fn sa_naive<T: Symbol, Idx: SaIndex>(s: &[T], sa: &mut [Idx]) {
+    let mut order: Vec<usize> = (0..s.len()).collect();
+    order.sort_by(|&a, &b| s[a..].cmp(&s[b..]));
+    for (entry, i) in sa.iter_mut().zip(order) {
+        *entry = Idx::from_usize(i);
+    }
+}
+
+/// Writes the suffix array of `s`, whose characters are all at most `upper`, into `sa`.
+fn sa_is<T: Symbol, Idx: SaIndex>(s: &[T], upper: usize, sa: &mut [Idx]) {
+    let n = s.len();
+    if n < NAIVE_THRESHOLD {
+        sa_naive(s, sa);
+        return;
+    }
+
+    let types = SuffixTypes::new(s);
+
+    // starts[c] is the index of the first suffix beginning with c, starts[c + 1] is one past the last
+    let mut starts = vec![Idx::from_usize(0); upper + 2];
+    for c in s {
+        let c = c.rank() + 1;
+        starts[c] = Idx::from_usize(starts[c].to_usize() + 1);
+    }
+    for c in 1..starts.len() {
+        starts[c] = Idx::from_usize(starts[c].to_usize() + starts[c - 1].to_usize());
+    }
+    let mut buckets = starts.clone();
+
+    // Sort the LMS substrings by placing the LMS positions at the ends of their buckets
+    for v in sa.iter_mut() {
+        *v = Idx::EMPTY;
+    }
+    let last = buckets.len() - 1;
+    buckets[..last].copy_from_slice(&starts[1..]);
+    let mut m = 0;
+    for i in (1..n).rev() {
+        if types.is_lms(i) {
+            let end = buckets[s[i].rank()].to_usize() - 1;
+            buckets[s[i].rank()] = Idx::from_usize(end);
+            sa[end] = Idx::from_usize(i);
+            m += 1;
+        }
+    }
+    induce(s, sa, &types, &starts, &mut buckets);
+
+    if m == 0 {
+        return;
+    }
+
+    // Sorted LMS suffixes move to the front, which never overtakes an unread entry
+    let mut k = 0;
+    for i in 0..n {
+        let v = sa[i];
+        if v != Idx::EMPTY && types.is_lms(v.to_usize()) {
+            sa[k] = v;
+            k += 1;
+        }
+    }
+
+    // Name the LMS substrings, equal substrings getting equal names. LMS positions are at
+    // least two apart, so each name fits into sa[m + pos / 2].
+    for v in sa[m..].iter_mut() {
+        *v = Idx::EMPTY;
+    }
+    let mut name = 0;
+    let mut prev = sa[0].to_usize();
+    let mut prev_end = types.next_lms(prev, n);
+    sa[m + prev / 2] = Idx::from_usize(0);
+    for i in 1..m {
+        let pos = sa[i].to_usize();
+        let end = types.next_lms(pos, n);
+        if !lms_substrings_equal(s, prev, prev_end, pos, end) {
+            name += 1;
+        }
+        sa[m + pos / 2] = Idx::from_usize(name);
+        prev = pos;
+        prev_end = end;
+    }
+
+    // Move the names to the end, keeping their text order, to form the reduced text
+    let mut k = n;
+    for j in (m..n).rev() {
+        if sa[j] != Idx::EMPTY {
+            k -= 1;
+            sa[k] = sa[j];
+        }
+    }
+
+    {
+        let (rec_sa, rec_s) = sa.split_at_mut(n - m);
+        if name + 1 < m {
+            sa_is(&rec_s[..], name, &mut rec_sa[..m]);
+        } else {
+            // All names are distinct, so they are the ranks already
+            for (i, rank) in rec_s.iter().enumerate() {
+                rec_sa[rank.to_usize()] = Idx::from_usize(i);
+            }
+        }
+    }
+
+    // Translate the sorted reduced suffixes back into LMS positions
+    let mut j = n - m;
+    for i in 1..n {
+        if types.is_lms(i) {
+            sa[j] = Idx::from_usize(i);
+            j += 1;
+        }
+    }
+    for i in 0..m {
+        sa[i] = sa[n - m + sa[i].to_usize()];
+    }
+
+    // Place the sorted LMS suffixes at the ends of their buckets. They are in order of their
+    // first character, so every target is at or after the entry being moved, and walking
+    // backwards never overwrites one that is still to be moved.
+    for v in sa[m..].iter_mut() {
+        *v = Idx::EMPTY;
+    }
+    let last = buckets.len() - 1;
+    buckets[..last].copy_from_slice(&starts[1..]);
+    for i in (0..m).rev() {
+        let d = sa[i];
+        sa[i] = Idx::EMPTY;
+        let c = s[d.to_usize()].rank();
+        let end = buckets[c].to_usize() - 1;
+        buckets[c] = Idx::from_usize(end);
+        sa[end] = d;
+    }
+    induce(s, sa, &types, &starts, &mut buckets);
+}
+
+/// Compares the LMS substrings `s[a..=end_a]` and `s[b..=end_b]`.
+/// A substring that runs into the end of the text is unique.
+fn lms_substrings_equal<T: Symbol>(s: &[T], a: usize, end_a: usize, b: usize, end_b: usize) -> bool {
+    let n = s.len();
+    if end_a - a != end_b - b || end_a == n || end_b == n {
+        return false;
+    }
+    s[a..=end_a] == s[b..=end_b]
+}
+
+/// Induces the order of every suffix from the LMS suffixes already placed at the ends of their buckets.
+fn induce<T: Symbol, Idx: SaIndex>(
+    s: &[T],
+    sa: &mut [Idx],
+    types: &SuffixTypes,
+    starts: &[Idx],
+    buckets: &mut [Idx],
+) {
+    let n = s.len();
+
+    // L-type suffixes, from the heads of the buckets. The last suffix follows the empty suffix.
+    buckets.copy_from_slice(starts);
+    let c = s[n - 1].rank();
+    sa[buckets[c].to_usize()] = Idx::from_usize(n - 1);
+    buckets[c] = Idx::from_usize(buckets[c].to_usize() + 1);
+    for i in 0..n {
+        let v = sa[i];
+        if v != Idx::EMPTY && v.to_usize() > 0 && !types.is_s(v.to_usize() - 1) {
+            let p = v.to_usize() - 1;
+            let c = s[p].rank();
+            sa[buckets[c].to_usize()] = Idx::from_usize(p);
+            buckets[c] = Idx::from_usize(buckets[c].to_usize() + 1);
+        }
+    }
+
+    // S-type suffixes, from the tails of the buckets
+    let last = buckets.len() - 1;
+    buckets[..last].copy_from_slice(&starts[1..]);
+    for i in (0..n).rev() {
+        let v = sa[i];
+        if v != Idx::EMPTY && v.to_usize() > 0 && types.is_s(v.to_usize() - 1) {
+            let p = v.to_usize() - 1;
+            let c = s[p].rank();
+            let end = buckets[c].to_usize() - 1;
+            buckets[c] = Idx::from_usize(end);
+            sa[end] = Idx::from_usize(p);
+        }
+    }
+}
@@ -0,0 +1,301 @@
+//!
+//! Vectorised versions of the byte loops bsdiff and bspatch spend their time in.
+//!
+//! Comparisons are done 64 bytes at a time into bit masks, using SSE2 or AVX2 on x86_64 and
+//! eight byte words elsewhere. Every function gives exactly the result of the scalar loop it
+//! replaces, whichever `Kernel` runs it.
+//!
+
+use std::cmp::min;
+use std::convert::TryInto;
+
+/// Bytes compared into one mask
+const BLOCK: usize = 64;
+
+///
+/// An instruction set to run the loops with.
+/// `Kernel::detect` picks the best one the running CPU supports. Kernels can only be had from
+/// `detect` and `available`, which check the CPU, so the unsafe paths never run without their
+/// instructions.
+///
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Kernel(Isa);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Isa {
+    /// Eight byte words, on any target
+    Portable,
+    #[cfg(target_arch = "x86_64")]
+    Sse2,
+    #[cfg(target_arch = "x86_64")]
+    Avx2,
+}
+
+impl Kernel {
+    pub fn detect() -> Kernel {
+        #[cfg(target_arch = "x86_64")]
+        {
+            if is_x86_feature_detected!("avx2") {
+                return Kernel(Isa::Avx2);
+            }
+            // SSE2 is part of the x86_64 baseline
+            Kernel(Isa::Sse2)
+        }
+        #[cfg(not(target_arch = "x86_64"))]
+        Kernel(Isa::Portable)
+    }
+
+    /// Every kernel the running CPU supports
+    pub fn available() -> Vec<Kernel> {
+        let mut kernels = vec![Kernel(Isa::Portable)];
+        #[cfg(target_arch = "x86_64")]
+        {
+            kernels.push(Kernel(Isa::Sse2));
+            if is_x86_feature_detected!("avx2") {
+                kernels.push(Kernel(Isa::Avx2));
+            }
+        }
+        kernels
+    }
+
+    /// Bit `i` is set when `a[i] == b[i]`, for the first `min(a.len(), b.len(), 64)` bytes
+    #[inline]
+    fn eq_mask(self, a: &[u8], b: &[u8]) -> u64 {
+        let len = min(min(a.len(), b.len()), BLOCK);
+        if len < BLOCK {
+            return (0..len)
+                .filter(|&i| a[i] == b[i])
+                .fold(0, |mask, i| mask | 1 << i);
+        }
+        let (a, b) = (&a[..BLOCK], &b[..BLOCK]);
+        match self.0 {
+            Isa::Portable => eq_mask_words(a, b),
+            // Both slices are BLOCK bytes long, and the kernel only exists if the CPU has them
+            #[cfg(target_arch = "x86_64")]
+            Isa::Sse2 => unsafe { x86::eq_mask_sse2(a, b) },
+            #[cfg(target_arch = "x86_64")]
+            Isa::Avx2 => unsafe { x86::eq_mask_avx2(a, b) },
+        }
+    }
+
+    /// The number of positions at which `a` and `b` hold the same byte
+    pub fn count_equal(self, a: &[u8], b: &[u8]) -> usize {
+        let len = min(a.len(), b.len());
+        if len < BLOCK {
+            // Most calls count a few bytes, which masks would only slow down
+            return a.iter().zip(b).filter(|(x, y)| x == y).count();
+        }
+        (0..len)
+            .step_by(BLOCK)
+            .map(|base| self.eq_mask(&a[base..len], &b[base..len]).count_ones() as usize)
+            .sum()
+    }
+
+    ///
+    /// The length of the prefix of `a` and `b` that is best copied with differences, as scored by
+    /// bsdiff: matching bytes count one, mismatching bytes count minus `penalty`, and the shortest
+    /// length with the highest positive score wins. 0 if nothing scores above 0.
+    ///
+    pub fn best_forward(self, a: &[u8], b: &[u8], penalty: u32) -> usize {
+        let len = min(a.len(), b.len());
+        best_prefix(len, penalty, |base| {
+            let equal = self.eq_mask(&a[base..len], &b[base..len]);
+            (equal, !equal & valid_bits(len - base))
+        })
+    }
+
+    /// Like `best_forward`, for the suffixes of `a` and `b`, which must be of equal length.
+    pub fn best_backward(self, a: &[u8], b: &[u8], penalty: u32) -> usize {
+        let len = min(a.len(), b.len());
+        best_prefix(len, penalty, |base| {
+            // The block ending `base` bytes before the end, with its first byte in the top bit
+            let block = min(BLOCK, len - base);
+            let start = len - base - block;
+            let equal = self.eq_mask(&a[start..start + block], &b[start..start + block]);
+            let equal = equal.reverse_bits() >> (BLOCK - block);
+            (equal, !equal & valid_bits(block))
+        })
+    }
+
+    ///
+    /// Where to split the overlap between a forward and a backward extension, as scored by
+    /// bsdiff: bytes where `new_f` matches `old_f` count one, bytes where `new_b` matches `old_b`
+    /// count minus one, and the shortest prefix with the highest positive score is returned.
+    ///
+    pub fn best_overlap(self, new_f: &[u8], old_f: &[u8], new_b: &[u8], old_b: &[u8]) -> usize {
+        let len = min(min(new_f.len(), old_f.len()), min(new_b.len(), old_b.len()));
+        best_prefix(len, 1, |base| {
+            (
+                self.eq_mask(&new_f[base..len], &old_f[base..len]),
+                self.eq_mask(&new_b[base..len], &old_b[base..len]),
+            )
+        })
+    }
+
+    /// Adds `src` to `dst` bytewise, wrapping on overflow.
+    pub fn add_bytes(self, dst: &mut [u8], src: &[u8]) {
+        // The kernel only holds AVX2 when `detect` or `available` found it
+        match self.0 {
+            #[cfg(target_arch = "x86_64")]
+            Isa::Avx2 => unsafe { x86::add_bytes_avx2(dst, src) },
+            _ => add_bytes_portable(dst, src),
+        }
+    }
+}
+
+/// The lowest `len` bits
+#[inline]
+fn valid_bits(len: usize) -> u64 {
+    if len >= BLOCK {
+        u64::MAX
+    } else {
+        (1 << len) - 1
+    }
+}
+
+///
+/// The first `i` in `1..=len` with the highest sum of scores over positions `0..i`, provided that
+/// sum is positive, else 0. Position `base + j` scores one if bit `j` of the first mask returned
+/// by `masks(base)` is set, and minus `penalty` if bit `j` of the second mask is set.
+///
+fn best_prefix<F: FnMut(usize) -> (u64, u64)>(len: usize, penalty: u32, mut masks: F) -> usize {
+    let penalty = i64::from(penalty);
+    let mut sum = 0i64;
+    let mut best = 0i64;
+    let mut best_len = 0;
+    for base in (0..len).step_by(BLOCK) {
+        let (plus, minus) = masks(base);
+        // The sum can only reach a new maximum where it goes up, and by at most `plus` set bits
+        if sum + plus.count_ones() as i64 > best {
+            let mut rising = plus & !minus;
+            while rising != 0 {
+                let j = rising.trailing_zeros() as usize;
+                let upto = valid_bits(j + 1);
+                let score = sum + (plus & upto).count_ones() as i64
+                    - penalty * (minus & upto).count_ones() as i64;
+                if score > best {
+                    best = score;
+                    best_len = base + j + 1;
+                }
+                rising &= rising - 1;
+            }
+        }
+        sum += plus.count_ones() as i64 - penalty * minus.count_ones() as i64;
+    }
+    best_len
+}
+
+#[inline]
+fn load(data: &[u8], at: usize) -> u64 {
+    u64::from_le_bytes(data[at..at + 8].try_into().unwrap())
+}
+
+/// Bit `i` is set when byte `i` of `x` is zero
+#[inline]
+fn zero_bytes(x: u64) -> u64 {
+    const LOW_SEVEN: u64 = 0x7f7f_7f7f_7f7f_7f7f;
+    let high = !(((x & LOW_SEVEN) + LOW_SEVEN) | x | LOW_SEVEN);
+    // Gathers the high bit of every byte into the top byte, byte 0 lowest
+    (high >> 7).wrapping_mul(0x0102_0408_1020_4080) >> 56
+}
+
+fn eq_mask_words(a: &[u8], b: &[u8]) -> u64 {
+    (0..BLOCK).step_by(8).fold(0, |mask, at| {
+        mask | zero_bytes(load(a, at) ^ load(b, at)) << at
+    })
+}
+
+fn add_bytes_portable(dst: &mut [u8], src: &[u8]) {
+    for (d, s) in dst.iter_mut().zip(src) {
+        *d = d.wrapping_add(*s);
+    }
+}
+
+///
+/// The length of the common prefix of `a` and `b`, compared a word at a time.
+/// Most suffixes compared in a search differ right away, so the first byte is checked on its own.
+///
+#[inline]
+pub fn matchlen(a: &[u8], b: &[u8]) -> usize {
+    let len = min(a.len(), b.len());
+    if len == 0 || a[0] != b[0] {
+        return 0;
+    }
+    let mut i = 0;
+    while i + 8 <= len {
+        let diff = load(a, i) ^ load(b, i);
+        if diff != 0 {
+            return i + diff.trailing_zeros() as usize / 8;
+        }
+        i += 8;
+    }
+    while i < len && a[i] == b[i] {
+        i += 1;
+    }
+    i
+}
+
+/// The length of the common suffix of `a` and `b`, compared a word at a time.
+pub fn matchlen_back(a: &[u8], b: &[u8]) -> usize {
+    let len = min(a.len(), b.len());
+    let (a, b) = (&a[a.len() - len..], &b[b.len() - len..]);
+    let mut i = 0;
+    while i + 8 <= len {
+        // The last byte in memory is the most significant
+        let diff = load(a, len - i - 8) ^ load(b, len - i - 8);
+        if diff != 0 {
+            return i + diff.leading_zeros() as usize / 8;
+        }
+        i += 8;
+    }
+    while i < len && a[len - i - 1] == b[len - i - 1] {
+        i += 1;
+    }
+    i
+}
+
+#[cfg(target_arch = "x86_64")]
+mod x86 {
+    use super::BLOCK;
+    use std::arch::x86_64::*;
+
+    #[target_feature(enable = "sse2")]
+    pub(super) unsafe fn eq_mask_sse2(a: &[u8], b: &[u8]) -> u64 {
+        let mut mask = 0;
+        for at in (0..BLOCK).step_by(16) {
+            let va = _mm_loadu_si128(a.as_ptr().add(at) as *const __m128i);
+            let vb = _mm_loadu_si128(b.as_ptr().add(at) as *const __m128i);
+            let equal = _mm_movemask_epi8(_mm_cmpeq_epi8(va, vb)) as u16;
+            mask |= (equal as u64) << at;
+        }
+        mask
+    }
+
+    #[target_feature(enable = "avx2")]
+    pub(super) unsafe fn eq_mask_avx2(a: &[u8], b: &[u8]) -> u64 {
+        let mut mask = 0;
+        for at in (0..BLOCK).step_by(32) {
+            let va = _mm256_loadu_si256(a.as_ptr().add(at) as *const __m256i);
+            let vb = _mm256_loadu_si256(b.as_ptr().add(at) as *const __m256i);
+            let equal = _mm256_movemask_epi8(_mm256_cmpeq_epi8(va, vb)) as u32;
+            mask |= (equal as u64) << at;
+        }
+        mask
+    }
+
+    #[target_feature(enable = "avx2")]
+    pub(super) unsafe fn add_bytes_avx2(dst: &mut [u8], src: &[u8]) {
+        let len = dst.len().min(src.len());
+        let mut i = 0;
+        while i + 32 <= len {
+            let d = _mm256_loadu_si256(dst.as_ptr().add(i) as *const __m256i);
+            let s = _mm256_loadu_si256(src.as_ptr().add(i) as *const __m256i);
+            _mm256_storeu_si256(
+                dst.as_mut_ptr().add(i) as *mut __m256i,
+                _mm256_add_epi8(d, s),
+            );
+            i += 32;
+        }
+        super::add_bytes_portable(&mut dst[i..len], &src[i..len]);
+    }
+}
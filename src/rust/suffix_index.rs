@@ -0,0 +1,181 @@
+//!
+//! Queries over the suffix array of a text.
+//!
+//! This is the same suffix array `bsdiff_raw` searches for matches, exposed for tools that need
+//! to locate byte strings in a base image without producing a patch.
+//!
+
+use super::bsdiff::{search, SearchTree};
+use super::sais::{sais, SaIndex};
+use std::cmp::min;
+
+/// Suffix array or LCP entries, 32 bit wide whenever the text allows it
+pub(crate) enum Entries {
+    Narrow(Vec<u32>),
+    Wide(Vec<u64>),
+}
+
+impl Entries {
+    fn get(&self, i: usize) -> usize {
+        match self {
+            Entries::Narrow(entries) => entries[i].to_usize(),
+            Entries::Wide(entries) => entries[i].to_usize(),
+        }
+    }
+}
+
+///
+/// The sorted suffixes of a text, answering substring queries in `O(m log n)` for a pattern
+/// of length `m`.
+///
+/// Suffixes are numbered by rank from `0` to `text.len()`; rank 0 is always the empty suffix.
+///
+pub struct SuffixIndex<'a> {
+    text: &'a [u8],
+    suffixes: Entries,
+    tree: SearchTree,
+    lcp: Option<Entries>,
+}
+
+impl<'a> SuffixIndex<'a> {
+    /// Sorts the suffixes of `text`.
+    pub fn new(text: &'a [u8]) -> Self {
+        // 32 bit suffix array entries halve the memory use, as long as every index fits
+        let suffixes = if text.len() < u32::MAX as usize {
+            Entries::Narrow(suffix_array(text))
+        } else {
+            Entries::Wide(suffix_array(text))
+        };
+        SuffixIndex::from_suffixes(text, suffixes)
+    }
+
+    pub(crate) fn from_suffixes(text: &'a [u8], suffixes: Entries) -> Self {
+        let tree = match &suffixes {
+            Entries::Narrow(suffixes) => SearchTree::new(suffixes, text),
+            Entries::Wide(suffixes) => SearchTree::new(suffixes, text),
+        };
+        SuffixIndex {
+            text,
+            suffixes,
+            tree,
+            lcp: None,
+        }
+    }
+
+    ///
+    /// Also computes the longest common prefix of every pair of neighbouring suffixes, using
+    /// Kasai's algorithm. This takes linear time and two more arrays the size of the index
+    /// while it runs, one of which is kept.
+    ///
+    pub fn with_lcp(mut self) -> Self {
+        let text = self.text;
+        self.lcp = Some(match &self.suffixes {
+            Entries::Narrow(suffixes) => Entries::Narrow(kasai(text, suffixes)),
+            Entries::Wide(suffixes) => Entries::Wide(kasai(text, suffixes)),
+        });
+        self
+    }
+
+    /// The text this index was built from
+    pub fn text(&self) -> &'a [u8] {
+        self.text
+    }
+
+    /// The start of the suffix with the given rank
+    pub fn suffix(&self, rank: usize) -> usize {
+        self.suffixes.get(rank)
+    }
+
+    ///
+    /// The length of the common prefix of the suffixes with ranks `rank - 1` and `rank`,
+    /// or 0 for rank 0. `None` unless the index was built `with_lcp`.
+    ///
+    pub fn lcp(&self, rank: usize) -> Option<usize> {
+        self.lcp.as_ref().map(|lcp| lcp.get(rank))
+    }
+
+    /// Every position at which `pattern` occurs in the text, in increasing order.
+    pub fn find_all(&self, pattern: &[u8]) -> Vec<usize> {
+        let mut positions = match &self.suffixes {
+            Entries::Narrow(suffixes) => prefixed_by(self.text, suffixes, pattern),
+            Entries::Wide(suffixes) => prefixed_by(self.text, suffixes, pattern),
+        };
+        positions.sort_unstable();
+        positions
+    }
+
+    ///
+    /// The longest prefix of `pattern` that occurs in the text, as its position in the text
+    /// and its length. This is the match `bsdiff_raw` would pick at the start of `pattern`.
+    ///
+    pub fn longest_match(&self, pattern: &[u8]) -> (usize, usize) {
+        let mut pos = 0;
+        let len = match &self.suffixes {
+            Entries::Narrow(suffixes) => search(&self.tree, suffixes, self.text, pattern, &mut pos),
+            Entries::Wide(suffixes) => search(&self.tree, suffixes, self.text, pattern, &mut pos),
+        };
+        (pos as usize, len as usize)
+    }
+
+    pub(crate) fn suffixes(&self) -> &Entries {
+        &self.suffixes
+    }
+
+    pub(crate) fn search_tree(&self) -> &SearchTree {
+        &self.tree
+    }
+}
+
+#[cfg(not(feature = "parallel"))]
+fn suffix_array<Idx: SaIndex>(text: &[u8]) -> Vec<Idx> {
+    sais(text)
+}
+
+/// Below this length the serial sort finishes before threads would pay off
+#[cfg(feature = "parallel")]
+const PARALLEL_THRESHOLD: usize = 1 << 16;
+
+#[cfg(feature = "parallel")]
+fn suffix_array<Idx: SaIndex>(text: &[u8]) -> Vec<Idx> {
+    // Both produce the one correct suffix array, so the choice never changes the patch
+    if text.len() < PARALLEL_THRESHOLD || rayon::current_num_threads() < 2 {
+        sais(text)
+    } else {
+        super::par_sufsort::par_sufsort(text)
+    }
+}
+
+/// The starts of all suffixes beginning with `pattern`, in suffix order
+fn prefixed_by<Idx: SaIndex>(text: &[u8], suffixes: &[Idx], pattern: &[u8]) -> Vec<usize> {
+    let prefix = |i: &Idx| {
+        let i = i.to_usize();
+        &text[i..min(i + pattern.len(), text.len())]
+    };
+    let start = suffixes.partition_point(|i| prefix(i) < pattern);
+    let end = start + suffixes[start..].partition_point(|i| prefix(i) == pattern);
+    suffixes[start..end].iter().map(|i| i.to_usize()).collect()
+}
+
+/// Kasai, Lee, Arimura, Arikawa and Park: going through the text in order, the common prefix with
+/// the preceding suffix shrinks by at most one byte per position, so comparisons never back up.
+fn kasai<Idx: SaIndex>(text: &[u8], suffixes: &[Idx]) -> Vec<Idx> {
+    let n = text.len();
+    let mut rank = vec![Idx::from_usize(0); n + 1];
+    for (r, i) in suffixes.iter().enumerate() {
+        rank[i.to_usize()] = Idx::from_usize(r);
+    }
+
+    let mut lcp = vec![Idx::from_usize(0); n + 1];
+    let mut h = 0;
+    for i in 0..n {
+        // Only the empty suffix has rank 0, so every other suffix has a predecessor
+        let r = rank[i].to_usize();
+        let j = suffixes[r - 1].to_usize();
+        while i + h < n && j + h < n && text[i + h] == text[j + h] {
+            h += 1;
+        }
+        lcp[r] = Idx::from_usize(h);
+        h = h.saturating_sub(1);
+    }
+    lcp
+}
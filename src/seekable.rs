@@ -0,0 +1,271 @@
+use crate::rust::{bsdiff_internal, bspatch_internal, BsDiffRequest, BsPatchRequest};
+use crate::{BsDiffError, BsDiffResult, DiffOptions, OldData, PatchOptions};
+use crate::{declared_len, JBsDiffStreams, MAGIC_NUMBER_BSDIFF_40};
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+use bzip2::bufread::BzDecoder;
+use bzip2::write::BzEncoder;
+use std::cell::RefCell;
+use std::cmp::min;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufReader, BufWriter, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// How much of each section is read from the source at a time
+const WINDOW_BUFFER_LEN: usize = 64 * 1024;
+
+///
+/// The bytes of a source from `pos` up to `end`. Every read seeks first, so several windows
+/// onto the same source can be read in turns.
+///
+struct Window<'a, R> {
+    source: &'a RefCell<R>,
+    pos: u64,
+    end: u64,
+}
+
+impl<'a, R: Read + Seek> Window<'a, R> {
+    fn new(source: &'a RefCell<R>, pos: u64, end: u64) -> BufReader<Self> {
+        BufReader::with_capacity(WINDOW_BUFFER_LEN, Window { source, pos, end })
+    }
+}
+
+impl<R: Read + Seek> Read for Window<'_, R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let len = min(buf.len() as u64, self.end.saturating_sub(self.pos)) as usize;
+        if len == 0 {
+            return Ok(0);
+        }
+        let mut source = self.source.borrow_mut();
+        source.seek(SeekFrom::Start(self.pos))?;
+        let read = source.read(&mut buf[..len])?;
+        self.pos += read as u64;
+        Ok(read)
+    }
+}
+
+type Section<'a, R> = BzDecoder<BufReader<Window<'a, R>>>;
+
+/// Same as `jbspatch40`, but decompresses the three sections straight from `patch` as they
+/// are needed rather than reading the whole patch into memory first.
+pub fn jbspatch40_seekable<W: Write, R: Read + Seek>(
+    old: &[u8],
+    new: W,
+    patch: R,
+) -> BsDiffResult<()> {
+    jbspatch40_seekable_with(old, new, patch, &PatchOptions::default())
+}
+
+///
+/// Same as `jbspatch40_with`, reading the patch from its current position in `patch` without
+/// buffering more than a few windows of it.
+///
+pub fn jbspatch40_seekable_with<W: Write, R: Read + Seek>(
+    old: &[u8],
+    new: W,
+    mut patch: R,
+    options: &PatchOptions,
+) -> BsDiffResult<()> {
+    let mut header = [0u8; 32];
+    patch
+        .read_exact(&mut header)
+        .map_err(BsDiffError::from_header)?;
+    if &header[..8] != MAGIC_NUMBER_BSDIFF_40.as_bytes() {
+        return Err(BsDiffError::BadMagic);
+    }
+    let mut header_iter = &header[8..];
+    let ctrl_len = header_iter.read_u64::<LittleEndian>()?;
+    let diff_len = header_iter.read_u64::<LittleEndian>()?;
+    let out_len = header_iter.read_u64::<LittleEndian>()?;
+
+    let limits = &options.limits;
+    limits.check_new_size(out_len)?;
+    let out_len = declared_len(out_len)?;
+    let held = 3 * WINDOW_BUFFER_LEN as u64;
+    limits.check_memory(held)?;
+
+    // Sections running past the end of the patch are cut short there and fail to decode
+    let ctrl_start = patch.stream_position()?;
+    let patch_end = patch.seek(SeekFrom::End(0))?;
+    let diff_start = min(ctrl_start.saturating_add(ctrl_len), patch_end);
+    let extra_start = min(diff_start.saturating_add(diff_len), patch_end);
+    let patch = RefCell::new(patch);
+
+    let streams = JBsDiffStreams {
+        ctrl_stream: BzDecoder::new(Window::new(&patch, ctrl_start, diff_start)),
+        diff_stream: BzDecoder::new(Window::new(&patch, diff_start, extra_start)),
+        extra_stream: BzDecoder::new(Window::new(&patch, extra_start, patch_end)),
+    };
+
+    let req = BsPatchRequest {
+        data: streams,
+        ctrl_stream: |data: &mut JBsDiffStreams<Section<R>>, buffer| {
+            data.ctrl_stream
+                .read_exact(buffer)
+                .map_err(BsDiffError::from_compressed)
+        },
+        diff_stream: |data, buffer| {
+            data.diff_stream
+                .read_exact(buffer)
+                .map_err(BsDiffError::from_compressed)
+        },
+        extra_stream: |data, buffer| {
+            data.extra_stream
+                .read_exact(buffer)
+                .map_err(BsDiffError::from_compressed)
+        },
+    };
+
+    bspatch_internal(old, new, out_len, req, true, held, options)?;
+    Ok(())
+}
+
+/// How much compressed output is collected before it is written out
+const SPOOL_BUFFER_LEN: usize = 64 * 1024;
+
+/// A file in the spool directory holding a section until it can be copied into the patch,
+/// closed and removed again when dropped
+struct Spool {
+    /// Only `None` while dropping, as Windows cannot remove a file that is still open
+    file: Option<File>,
+    path: PathBuf,
+}
+
+impl Spool {
+    fn new(dir: &Path) -> io::Result<Spool> {
+        static COUNT: AtomicUsize = AtomicUsize::new(0);
+        loop {
+            let name = format!(
+                "bsdiff-rs-{}-{}.spool",
+                std::process::id(),
+                COUNT.fetch_add(1, Ordering::Relaxed)
+            );
+            let path = dir.join(name);
+            let opened = OpenOptions::new()
+                .read(true)
+                .write(true)
+                .create_new(true)
+                .open(&path);
+            match opened {
+                Ok(file) => {
+                    return Ok(Spool {
+                        file: Some(file),
+                        path,
+                    })
+                }
+                Err(err) if err.kind() == io::ErrorKind::AlreadyExists => continue,
+                Err(err) => return Err(err),
+            }
+        }
+    }
+
+    fn file(&mut self) -> &mut File {
+        self.file.as_mut().expect("Spool file is open until dropped")
+    }
+
+    /// Copies everything written so far to `patch`, returning its length
+    fn copy_to<W: Write>(&mut self, patch: &mut W) -> io::Result<u64> {
+        let file = self.file();
+        file.seek(SeekFrom::Start(0))?;
+        io::copy(file, patch)
+    }
+}
+
+impl Drop for Spool {
+    fn drop(&mut self) {
+        drop(self.file.take());
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+type Spooled<'a> = BzEncoder<Box<dyn Write + 'a>>;
+
+fn spooled<'a, S: Write + 'a>(sink: S, options: &DiffOptions) -> Spooled<'a> {
+    let buffered = BufWriter::with_capacity(SPOOL_BUFFER_LEN, sink);
+    BzEncoder::new(Box::new(buffered), options.compression.bzip2())
+}
+
+/// Same as `jbsdiff40`, but writes the sections to `patch` as they are compressed rather than
+/// collecting all three in memory first.
+pub fn jbsdiff40_seekable<O: OldData + ?Sized, W: Write + Seek>(
+    old: &O,
+    new: &[u8],
+    patch: W,
+) -> BsDiffResult<()> {
+    jbsdiff40_seekable_with(old, new, patch, &DiffOptions::default())
+}
+
+///
+/// Same as `jbsdiff40_with`, writing the patch from the current position in `patch`. The ctrl
+/// section goes straight to `patch`, the diff and extra sections to temporary files in
+/// `std::env::temp_dir()` until it is complete, and the header is filled in last.
+///
+/// The temporary directory is often a tmpfs held in memory, in which case
+/// `jbsdiff40_seekable_in` should be given a directory on disk instead.
+/// Invalid `options` are rejected before anything is written. Any later error leaves `patch`
+/// holding a partial patch: the 32 zero bytes standing in for the header, followed by whatever
+/// of the sections was written by then. It starts with zeros rather than a magic number, so it
+/// is never taken for a patch, but it is up to the caller to truncate or discard it.
+///
+pub fn jbsdiff40_seekable_with<O: OldData + ?Sized, W: Write + Seek>(
+    old: &O,
+    new: &[u8],
+    patch: W,
+    options: &DiffOptions,
+) -> BsDiffResult<()> {
+    jbsdiff40_seekable_in(old, new, patch, options, &std::env::temp_dir())
+}
+
+///
+/// Same as `jbsdiff40_seekable_with`, spooling the diff and extra sections to files in
+/// `spool_dir` rather than the temporary directory. The files are named
+/// `bsdiff-rs-<pid>-<n>.spool` and removed again before this returns, also on error.
+///
+pub fn jbsdiff40_seekable_in<O: OldData + ?Sized, W: Write + Seek>(
+    old: &O,
+    new: &[u8],
+    mut patch: W,
+    options: &DiffOptions,
+    spool_dir: &Path,
+) -> BsDiffResult<()> {
+    options.validate()?;
+    let start = patch.stream_position()?;
+    patch.write_all(&[0u8; 32])?;
+    let mut diff_spool = Spool::new(spool_dir)?;
+    let mut extra_spool = Spool::new(spool_dir)?;
+    {
+        let streams = JBsDiffStreams {
+            ctrl_stream: spooled(&mut patch, options),
+            diff_stream: spooled(diff_spool.file(), options),
+            extra_stream: spooled(extra_spool.file(), options),
+        };
+
+        let req = BsDiffRequest {
+            data: streams,
+            ctrl_stream: |data: &mut JBsDiffStreams<Spooled>, buffer| {
+                Ok(data.ctrl_stream.write_all(buffer)?)
+            },
+            diff_stream: |data, buffer| Ok(data.diff_stream.write_all(buffer)?),
+            extra_stream: |data, buffer| Ok(data.extra_stream.write_all(buffer)?),
+        };
+
+        let streams = bsdiff_internal(old, new, req, true, options)?;
+        streams.ctrl_stream.finish()?.flush()?;
+        streams.diff_stream.finish()?.flush()?;
+        streams.extra_stream.finish()?.flush()?;
+    }
+
+    let ctrl_len = patch.stream_position()? - start - 32;
+    let diff_len = diff_spool.copy_to(&mut patch)?;
+    extra_spool.copy_to(&mut patch)?;
+    let end = patch.stream_position()?;
+
+    patch.seek(SeekFrom::Start(start))?;
+    patch.write_all(MAGIC_NUMBER_BSDIFF_40.as_bytes())?;
+    patch.write_u64::<LittleEndian>(ctrl_len)?;
+    patch.write_u64::<LittleEndian>(diff_len)?;
+    patch.write_u64::<LittleEndian>(new.len() as u64)?;
+    patch.seek(SeekFrom::Start(end))?;
+
+    Ok(())
+}
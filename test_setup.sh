@@ -6,6 +6,29 @@ mkdir target/c
 cc bsdiff-43/bsdiff.c -DBSDIFF_EXECUTABLE -lbz2 -o target/c/bsdiff && \
     cc bsdiff-43/bspatch.c -DBSPATCH_EXECUTABLE -lbz2 -o target/c/bspatch || echo "BsDiff C Build Failed!"
 
+# Link stock bsdiff 4.3 by Colin Percival, as packaged by most distributions, for BSDIFF40
+rm -rf target/percival
+mkdir target/percival
+if command -v bsdiff > /dev/null && command -v bspatch > /dev/null
+then
+    ln -s "$(command -v bsdiff)" target/percival/bsdiff
+    ln -s "$(command -v bspatch)" target/percival/bspatch
+else
+    echo "Stock bsdiff 4.3 Not Found!"
+fi
+
+# Link Android's bsdiff for BSDF2, built from https://android.googlesource.com/platform/external/bsdiff
+# into the directory named by ANDROID_BSDIFF_DIR
+rm -rf target/android
+mkdir target/android
+if [ -x "$ANDROID_BSDIFF_DIR/bsdiff" ] && [ -x "$ANDROID_BSDIFF_DIR/bspatch" ]
+then
+    ln -s "$(realpath "$ANDROID_BSDIFF_DIR")/bsdiff" target/android/bsdiff
+    ln -s "$(realpath "$ANDROID_BSDIFF_DIR")/bspatch" target/android/bspatch
+else
+    echo "Android bsdiff Not Found! (Set ANDROID_BSDIFF_DIR)"
+fi
+
 
 # Compile Java jbsdiff
 if mvn -f jbsdiff/pom.xml clean package
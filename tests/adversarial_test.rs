@@ -0,0 +1,109 @@
+use bsdiff_rs::rust::{bsdiff_raw_with, bspatch_raw};
+use bsdiff_rs::{DiffOptions, OldIndex};
+use rand::Rng;
+use std::time::{Duration, Instant};
+
+/// Far longer than any of these takes now, and far shorter than they took when quadratic
+const TIME_LIMIT: Duration = Duration::from_secs(30);
+
+const LEN: usize = 1 << 20;
+
+fn generate_data(seed: u128, length: usize) -> Vec<u8> {
+    let mut rng = rand_pcg::Pcg64Mcg::new(seed);
+    (0..length).map(|_| rng.gen()).collect()
+}
+
+fn zeros(length: usize) -> Vec<u8> {
+    vec![0; length]
+}
+
+fn periodic(length: usize, period: usize) -> Vec<u8> {
+    (0..length).map(|i| (i % period) as u8).collect()
+}
+
+/// Zeros with a marker byte every `step` bytes from `start`, like a sparse disk image
+fn sparse(length: usize, start: usize, step: usize) -> Vec<u8> {
+    let mut data = zeros(length);
+    for i in (start..length).step_by(step) {
+        data[i] = 1;
+    }
+    data
+}
+
+fn check(name: &str, old: &[u8], new: &[u8]) {
+    // An index skips trimming the shared ends, so the whole of `new` is scanned
+    let index = OldIndex::new(old);
+    for options in &[DiffOptions::default(), DiffOptions::smallest()] {
+        let start = Instant::now();
+        let mut patch = Vec::new();
+        bsdiff_raw_with(&index, new, &mut patch, options).unwrap();
+        let elapsed = start.elapsed();
+        assert!(elapsed < TIME_LIMIT, "{} took {:?}", name, elapsed);
+
+        let mut generated = vec![0; new.len()];
+        bspatch_raw(old, &mut generated, &patch[..]).unwrap();
+        assert!(generated == new, "{} patched incorrectly", name);
+    }
+}
+
+#[test]
+fn runs_shifted_by_one() {
+    check(
+        "leading byte",
+        &zeros(LEN),
+        &[&[1u8][..], &zeros(LEN)].concat(),
+    );
+    check(
+        "middle byte",
+        &zeros(LEN),
+        &[&zeros(LEN / 2)[..], &[1], &zeros(LEN / 2)].concat(),
+    );
+}
+
+#[test]
+fn runs_longer_than_old() {
+    check("doubled", &zeros(LEN / 2), &zeros(LEN));
+    let header = generate_data(1, 4096);
+    check(
+        "grown image",
+        &[&header[..], &zeros(LEN / 2)].concat(),
+        &[&header[..], &zeros(LEN)].concat(),
+    );
+}
+
+#[test]
+fn repeated_records() {
+    let record = generate_data(2, 61);
+    let old: Vec<u8> = record.iter().cycle().take(LEN).cloned().collect();
+    let mut new = old.clone();
+    new.insert(LEN / 3, 7);
+    new[2 * LEN / 3] ^= 1;
+    check("records", &old, &new);
+    check(
+        "periodic",
+        &periodic(LEN, 7),
+        &[&periodic(LEN / 2, 7)[..], &[9], &periodic(LEN / 2, 7)].concat(),
+    );
+}
+
+#[test]
+fn sparse_images() {
+    check("sparse", &sparse(LEN, 0, 4096), &sparse(LEN, 100, 4000));
+    check("sparse shifted", &sparse(LEN, 0, 64), &sparse(LEN, 3, 64));
+}
+
+#[test]
+fn seeks_to_the_ends_of_the_position_range() {
+    let old = generate_data(1, 64);
+    for &seek in &[i64::MIN, i64::MIN + 1, -(old.len() as i64) - 8] {
+        // Seek far before `old`, then diff 8 bytes there, which read nothing of it
+        let mut patch = Vec::new();
+        for &value in &[0, 0, seek, 8, 0, 0] {
+            patch.extend_from_slice(&i64::to_le_bytes(value));
+        }
+        patch.extend_from_slice(b"abcdefgh");
+        let mut new = vec![0; 8];
+        bspatch_raw(&old, &mut new, &patch[..]).unwrap();
+        assert!(new == b"abcdefgh");
+    }
+}
@@ -1,4 +1,4 @@
-use bsdiff_rs::{bsdiff_raw, bspatch_raw};
+use bsdiff_rs::{bsdiff_raw, bspatch_raw, bspatch_raw_with, PatchOptions};
 use rand::Rng;
 
 #[macro_use]
@@ -28,6 +28,12 @@ pub fn diff_patch_test(old: &[u8], new: &[u8]) {
     bspatch_raw(old, &mut generated, &patch[..]).expect("Failed to patch");
 
     assert_eq!(&generated[..], new);
+
+    let mut generated: Vec<u8> = vec![0; new.len()];
+    bspatch_raw_with(old, &mut generated, &patch[..], &PatchOptions::strict())
+        .expect("Failed to patch in strict mode");
+
+    assert_eq!(&generated[..], new);
 }
 
 const HARDCODED_DATA: (&[u8], &[u8]) = (
@@ -0,0 +1,157 @@
+use bsdiff_rs::{bsdf2_diff, bsdf2_diff_with, bsdf2_patch, Bsdf2Codecs};
+use bsdiff_rs::{BsDiffError, Codec, DiffOptions};
+use rand::Rng;
+use std::convert::TryInto;
+
+fn generate_data(seed: u128, length: usize) -> Vec<u8> {
+    let mut rng = rand_pcg::Pcg64Mcg::new(seed);
+    (0..length).map(|_| rng.gen_range(0, 16)).collect()
+}
+
+/// An edited copy of `old` with blocks moved backwards, so some seeks are negative
+fn pair() -> (Vec<u8>, Vec<u8>) {
+    let old = generate_data(1, 40_000);
+    let mut new = old[30_000..].to_vec();
+    new.extend_from_slice(&old[..20_000]);
+    new[100..300].copy_from_slice(&generate_data(3, 200));
+    new.splice(15_000..15_000, generate_data(5, 1000));
+    (old, new)
+}
+
+fn diff(old: &[u8], new: &[u8], codecs: &Bsdf2Codecs) -> Vec<u8> {
+    let mut patch = Vec::new();
+    bsdf2_diff(old, new, &mut patch, codecs).unwrap();
+    let mut generated = Vec::new();
+    bsdf2_patch(old, &mut generated, &patch[..]).unwrap();
+    assert!(generated == new);
+    patch
+}
+
+fn all(codec: Codec) -> Bsdf2Codecs {
+    Bsdf2Codecs {
+        ctrl: codec,
+        diff: codec,
+        extra: codec,
+    }
+}
+
+#[test]
+fn header_records_each_codec() {
+    let (old, new) = pair();
+    let patch = diff(&old, &new, &Bsdf2Codecs::default());
+    assert!(&patch[..8] == b"BSDF2\x01\x01\x01");
+    assert_eq!(&patch[24..32], &(new.len() as u64).to_le_bytes());
+    assert!(&patch[32..35] == b"BZh");
+
+    let codecs = Bsdf2Codecs {
+        ctrl: Codec::None,
+        ..Bsdf2Codecs::default()
+    };
+    let patch = diff(&old, &new, &codecs);
+    assert!(&patch[..8] == b"BSDF2\x00\x01\x01");
+    let ctrl_len = u64::from_le_bytes(patch[8..16].try_into().unwrap()) as usize;
+    assert!(&patch[32 + ctrl_len..35 + ctrl_len] == b"BZh");
+}
+
+#[test]
+fn controls_are_sign_magnitude() {
+    let (old, new) = pair();
+    let patch = diff(&old, &new, &all(Codec::None));
+    let ctrl_len = u64::from_le_bytes(patch[8..16].try_into().unwrap()) as usize;
+    let seeks = patch[32..32 + ctrl_len].chunks(24).map(|ctrl| &ctrl[16..]);
+    // Moving back to the start of old is a negative seek with the top bit set
+    assert!(seeks.clone().any(|seek| seek[7] == 0x80));
+    assert!(seeks.clone().all(|seek| seek[7] & 0x7f == 0));
+}
+
+#[test]
+fn applies_handmade_patch() {
+    let old = b"0123456789";
+    let ctrl: &[u8] = &[
+        4, 0, 0, 0, 0, 0, 0, 0, // diff 4 bytes
+        2, 0, 0, 0, 0, 0, 0, 0, // then 2 extra bytes
+        4, 0, 0, 0, 0, 0, 0, 0x80, // then seek back by 4
+        3, 0, 0, 0, 0, 0, 0, 0, // diff 3 bytes
+        0, 0, 0, 0, 0, 0, 0, 0, // no extra bytes
+        0, 0, 0, 0, 0, 0, 0, 0, // no seek
+    ];
+    let diff = [0, 0, 1, 0, 0, 0, 2];
+    let patch = [
+        &b"BSDF2\x00\x00\x00"[..],
+        &(ctrl.len() as u64).to_le_bytes(),
+        &(diff.len() as u64).to_le_bytes(),
+        &9u64.to_le_bytes(),
+        ctrl,
+        &diff,
+        b"xy",
+    ]
+    .concat();
+    let mut new = Vec::new();
+    bsdf2_patch(old, &mut new, &patch[..]).unwrap();
+    assert!(new == b"0133xy014");
+}
+
+#[test]
+fn none_round_trips() {
+    let (old, new) = pair();
+    diff(&old, &new, &all(Codec::None));
+}
+
+#[test]
+fn options_apply() {
+    let (old, new) = pair();
+    let mut patch = Vec::new();
+    bsdf2_diff_with(
+        &old,
+        &new,
+        &mut patch,
+        &Bsdf2Codecs::default(),
+        &DiffOptions::smallest(),
+    )
+    .unwrap();
+    let mut generated = Vec::new();
+    bsdf2_patch(&old, &mut generated, &patch[..]).unwrap();
+    assert!(generated == new);
+}
+
+#[cfg(feature = "brotli")]
+#[test]
+fn brotli_round_trips() {
+    let (old, new) = pair();
+    let patch = diff(&old, &new, &all(Codec::Brotli(9)));
+    assert!(&patch[..8] == b"BSDF2\x02\x02\x02");
+    let mixed = Bsdf2Codecs {
+        ctrl: Codec::None,
+        diff: Codec::Bzip2,
+        extra: Codec::Brotli(11),
+    };
+    diff(&old, &new, &mixed);
+}
+
+#[cfg(not(feature = "brotli"))]
+#[test]
+fn brotli_needs_its_feature() {
+    let (old, new) = pair();
+    let mut patch = Vec::new();
+    match bsdf2_diff(&old, &new, &mut patch, &all(Codec::Brotli(11))) {
+        Err(BsDiffError::UnsupportedCodec(_)) => {}
+        other => panic!("{:?}", other),
+    }
+}
+
+#[test]
+fn other_codecs_are_rejected() {
+    let (old, new) = pair();
+    let mut patch = Vec::new();
+    match bsdf2_diff(&old, &new, &mut patch, &all(Codec::Zstd(3))) {
+        Err(BsDiffError::InvalidOptions(_)) => {}
+        other => panic!("{:?}", other),
+    }
+
+    let mut patch = diff(&old, &new, &Bsdf2Codecs::default());
+    patch[6] = 3;
+    match bsdf2_patch(&old, Vec::new(), &patch[..]) {
+        Err(BsDiffError::BadMagic) => {}
+        other => panic!("{:?}", other),
+    }
+}
@@ -0,0 +1,99 @@
+use bsdiff_rs::{bsdiff40, bsdiff40_vec, bsdiff40_with, bspatch40, bspatch40_vec};
+use bsdiff_rs::{jbspatch40_vec, BsDiffError, DiffOptions};
+use bzip2::read::BzDecoder;
+use rand::Rng;
+use std::convert::TryInto;
+use std::io::Read;
+
+// See tests/fixtures/bsdiff40/README.md for where the patches come from
+const MOVED_OLD: &[u8] = include_bytes!("fixtures/bsdiff40/moved.old");
+const MOVED_NEW: &[u8] = include_bytes!("fixtures/bsdiff40/moved.new");
+const MOVED_PATCH: &[u8] = include_bytes!("fixtures/bsdiff40/moved.patch");
+const INSERTED_NEW: &[u8] = include_bytes!("fixtures/bsdiff40/inserted.new");
+const INSERTED_PATCH: &[u8] = include_bytes!("fixtures/bsdiff40/inserted.patch");
+
+fn generate_data(seed: u128, length: usize) -> Vec<u8> {
+    let mut rng = rand_pcg::Pcg64Mcg::new(seed);
+    (0..length).map(|_| rng.gen_range(0, 16)).collect()
+}
+
+/// The decompressed control entries of a `BSDIFF40` patch
+fn controls(patch: &[u8]) -> Vec<u8> {
+    let ctrl_len = u64::from_le_bytes(patch[8..16].try_into().unwrap()) as usize;
+    let mut ctrl = Vec::new();
+    BzDecoder::new(&patch[32..32 + ctrl_len])
+        .read_to_end(&mut ctrl)
+        .unwrap();
+    ctrl
+}
+
+#[test]
+fn fixtures_apply() {
+    assert!(bspatch40_vec(MOVED_OLD, MOVED_PATCH).unwrap() == MOVED_NEW);
+    assert!(bspatch40_vec(&[], INSERTED_PATCH).unwrap() == INSERTED_NEW);
+}
+
+#[test]
+fn fixtures_round_trip() {
+    for &(old, new) in &[(MOVED_OLD, MOVED_NEW), (&[][..], INSERTED_NEW)] {
+        let patch = bsdiff40_vec(old, new).unwrap();
+        assert!(bspatch40_vec(old, &patch[..]).unwrap() == new);
+
+        let mut patch = Vec::new();
+        bsdiff40_with(old, new, &mut patch, &DiffOptions::smallest()).unwrap();
+        assert!(bspatch40_vec(old, &patch[..]).unwrap() == new);
+    }
+}
+
+#[test]
+fn negative_seeks_are_sign_magnitude() {
+    let seeks = |patch: &[u8]| {
+        let ctrl = controls(patch);
+        let seeks = ctrl.chunks(24).map(|entry| entry[16..].to_vec());
+        seeks.collect::<Vec<_>>()
+    };
+    // Blocks of the fixture are out of order, so its patch seeks backwards at least once
+    let fixture = seeks(MOVED_PATCH);
+    assert!(fixture.iter().any(|seek| seek[7] == 0x80 && seek[..7] != [0; 7]));
+    assert!(fixture.iter().all(|seek| seek[7] & 0x7f == 0));
+
+    let old = generate_data(1, 30_000);
+    let new = [&old[20_000..], &old[..15_000]].concat();
+    let mut patch = Vec::new();
+    bsdiff40(&old, &new, &mut patch).unwrap();
+    assert!(seeks(&patch).iter().any(|seek| seek[7] == 0x80));
+    assert!(seeks(&patch).iter().all(|seek| seek[7] & 0x7f == 0));
+
+    let mut generated = Vec::new();
+    bspatch40(&old, &mut generated, &patch[..]).unwrap();
+    assert!(generated == new);
+}
+
+#[test]
+fn differs_from_jbsdiff40_only_in_negative_seeks() {
+    // The encodings agree on everything else
+    assert!(jbspatch40_vec(&[], INSERTED_PATCH).unwrap() == INSERTED_NEW);
+    let moved = jbspatch40_vec(MOVED_OLD, MOVED_PATCH);
+    assert!(moved.map_or(true, |new| new != MOVED_NEW));
+}
+
+#[test]
+fn damaged_headers_are_rejected() {
+    let mut patch = MOVED_PATCH.to_vec();
+    patch[..8].copy_from_slice(b"BSDIFF43");
+    match bspatch40_vec(MOVED_OLD, &patch[..]) {
+        Err(BsDiffError::BadMagic) => {}
+        other => panic!("{:?}", other.map(|new| new.len())),
+    }
+    match bspatch40_vec(MOVED_OLD, &MOVED_PATCH[..20]) {
+        Err(BsDiffError::TruncatedHeader) => {}
+        other => panic!("{:?}", other.map(|new| new.len())),
+    }
+    // A negative length
+    let mut patch = MOVED_PATCH.to_vec();
+    patch[15] = 0x80;
+    match bspatch40_vec(MOVED_OLD, &patch[..]) {
+        Err(BsDiffError::InvalidControl) => {}
+        other => panic!("{:?}", other.map(|new| new.len())),
+    }
+}
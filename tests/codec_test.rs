@@ -0,0 +1,135 @@
+use bsdiff_rs::{bsdiff43_vec, bsdiff43_with, bspatch43_vec};
+use bsdiff_rs::{BsDiffError, Codec, Compression, DiffOptions};
+use rand::Rng;
+
+fn generate_data(seed: u128, length: usize) -> Vec<u8> {
+    let mut rng = rand_pcg::Pcg64Mcg::new(seed);
+    (0..length).map(|_| rng.gen_range(0, 16)).collect()
+}
+
+fn pair() -> (Vec<u8>, Vec<u8>) {
+    let old = generate_data(1, 50_000);
+    let mut new = old.clone();
+    new[100..300].copy_from_slice(&generate_data(3, 200));
+    new.splice(20_000..20_000, generate_data(5, 1000));
+    new.drain(40_000..41_000);
+    (old, new)
+}
+
+fn diff(old: &[u8], new: &[u8], codec: Codec) -> Result<Vec<u8>, BsDiffError> {
+    let options = DiffOptions {
+        codec,
+        ..DiffOptions::default()
+    };
+    let mut patch = Vec::new();
+    bsdiff43_with(old, new, &mut patch, &options)?;
+    Ok(patch)
+}
+
+/// Checks the variant header and that the patch applies
+fn round_trip(codec: Codec, id: u8) {
+    let (old, new) = pair();
+    let patch = diff(&old, &new, codec).unwrap();
+    assert!(&patch[..16] == b"ENDSLEY/BSDIFC43");
+    assert_eq!(patch[16], id);
+    assert_eq!(&patch[17..25], &(new.len() as u64).to_le_bytes());
+    assert!(bspatch43_vec(&old, &patch[..]).unwrap() == new);
+}
+
+#[test]
+fn bzip2_keeps_the_original_format() {
+    let (old, new) = pair();
+    let patch = diff(&old, &new, Codec::Bzip2).unwrap();
+    assert!(&patch[..16] == b"ENDSLEY/BSDIFF43");
+    assert!(patch == bsdiff43_vec(&old, &new).unwrap());
+}
+
+#[test]
+fn none_round_trips() {
+    round_trip(Codec::None, 0);
+}
+
+#[test]
+fn bzip2_level_only_applies_to_bzip2() {
+    let (old, new) = pair();
+    let patch = |compression| {
+        let options = DiffOptions {
+            compression,
+            codec: Codec::None,
+            ..DiffOptions::default()
+        };
+        let mut patch = Vec::new();
+        bsdiff43_with(&old, &new, &mut patch, &options).unwrap();
+        patch
+    };
+    assert!(patch(Compression::fast()) == patch(Compression::best()));
+}
+
+#[cfg(feature = "gzip")]
+#[test]
+fn gzip_round_trips() {
+    round_trip(Codec::Gzip(9), 2);
+}
+
+#[cfg(feature = "xz")]
+#[test]
+fn xz_round_trips() {
+    round_trip(Codec::Xz(9), 3);
+}
+
+#[cfg(feature = "zstd")]
+#[test]
+fn zstd_round_trips() {
+    round_trip(Codec::Zstd(19), 4);
+}
+
+#[cfg(feature = "brotli")]
+#[test]
+fn brotli_round_trips() {
+    round_trip(Codec::Brotli(11), 5);
+}
+
+#[test]
+fn missing_codecs_are_reported() {
+    let (old, new) = pair();
+    let codecs = [
+        Codec::Gzip(6),
+        Codec::Xz(6),
+        Codec::Zstd(3),
+        Codec::Brotli(11),
+    ];
+    for &codec in codecs.iter().filter(|codec| !codec.available()) {
+        match diff(&old, &new, codec) {
+            Err(BsDiffError::UnsupportedCodec(_)) => {}
+            other => panic!("{:?} diffed: {:?}", codec, other.map(|patch| patch.len())),
+        }
+    }
+}
+
+#[test]
+fn unknown_codecs_are_rejected() {
+    let (old, new) = pair();
+    let mut patch = diff(&old, &new, Codec::None).unwrap();
+    patch[16] = 200;
+    match bspatch43_vec(&old, &patch[..]) {
+        Err(BsDiffError::BadMagic) => {}
+        other => panic!("{:?}", other.map(|new| new.len())),
+    }
+}
+
+#[test]
+fn levels_out_of_range_are_rejected() {
+    let (old, new) = pair();
+    let codecs = [
+        Codec::Gzip(10),
+        Codec::Xz(10),
+        Codec::Zstd(0),
+        Codec::Brotli(12),
+    ];
+    for &codec in codecs.iter().filter(|codec| codec.available()) {
+        match diff(&old, &new, codec) {
+            Err(BsDiffError::InvalidOptions(_)) => {}
+            other => panic!("{:?} diffed: {:?}", codec, other.map(|patch| patch.len())),
+        }
+    }
+}
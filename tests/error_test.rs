@@ -0,0 +1,66 @@
+use bsdiff_rs::{bsdiff43_vec, bspatch43_vec, jbsdiff40_vec, jbspatch40_vec, BsDiffError};
+
+const OLD: &[u8] = b"the quick brown fox jumps over the lazy dog";
+const NEW: &[u8] = b"the quick brown cat jumps over the lazy dog!";
+
+#[test]
+fn bspatch43_bad_magic() {
+    let mut patch = bsdiff43_vec(OLD, NEW).unwrap();
+    patch[0] = b'X';
+    match bspatch43_vec(OLD, &patch[..]) {
+        Err(BsDiffError::BadMagic) => {}
+        other => panic!("Expected BadMagic, got {:?}", other),
+    }
+}
+
+#[test]
+fn bspatch43_truncated_header() {
+    let patch = bsdiff43_vec(OLD, NEW).unwrap();
+    match bspatch43_vec(OLD, &patch[..20]) {
+        Err(BsDiffError::TruncatedHeader) => {}
+        other => panic!("Expected TruncatedHeader, got {:?}", other),
+    }
+}
+
+#[test]
+fn bspatch43_corrupt_stream() {
+    let mut patch = bsdiff43_vec(OLD, NEW).unwrap();
+    for byte in &mut patch[28..] {
+        *byte = !*byte;
+    }
+    match bspatch43_vec(OLD, &patch[..]) {
+        Err(BsDiffError::CorruptStream(_)) => {}
+        other => panic!("Expected CorruptStream, got {:?}", other),
+    }
+}
+
+#[test]
+fn jbspatch40_bad_magic() {
+    let mut patch = jbsdiff40_vec(OLD, NEW).unwrap();
+    patch[0] = b'X';
+    match jbspatch40_vec(OLD, &patch[..]) {
+        Err(BsDiffError::BadMagic) => {}
+        other => panic!("Expected BadMagic, got {:?}", other),
+    }
+}
+
+#[test]
+fn jbspatch40_truncated_header() {
+    let patch = jbsdiff40_vec(OLD, NEW).unwrap();
+    match jbspatch40_vec(OLD, &patch[..31]) {
+        Err(BsDiffError::TruncatedHeader) => {}
+        other => panic!("Expected TruncatedHeader, got {:?}", other),
+    }
+}
+
+#[test]
+fn jbspatch40_corrupt_stream() {
+    let mut patch = jbsdiff40_vec(OLD, NEW).unwrap();
+    for byte in &mut patch[36..] {
+        *byte = !*byte;
+    }
+    match jbspatch40_vec(OLD, &patch[..]) {
+        Err(BsDiffError::CorruptStream(_)) => {}
+        other => panic!("Expected CorruptStream, got {:?}", other),
+    }
+}
@@ -0,0 +1,26 @@
+#!/bin/sh
+# Rewrites the patches in this directory with stock bsdiff 4.3 and checks each of them with
+# the bsdiff4 Python package. Record the versions printed at the end in README.md.
+set -e
+cd "$(dirname "$0")"
+
+empty=$(mktemp)
+trap 'rm -f "$empty"' EXIT
+
+bsdiff moved.old moved.new moved.patch
+bsdiff "$empty" inserted.new inserted.patch
+
+python3 - "$empty" <<'PY'
+import sys
+import bsdiff4
+
+for old, name in [("moved.old", "moved"), (sys.argv[1], "inserted")]:
+    with open(old, "rb") as f:
+        old = f.read()
+    with open(name + ".new", "rb") as f:
+        new = f.read()
+    with open(name + ".patch", "rb") as f:
+        assert bsdiff4.patch(old, f.read()) == new, name
+print("bsdiff4", bsdiff4.__version__)
+PY
+command -v dpkg-query >/dev/null && dpkg-query -W bsdiff || true
@@ -0,0 +1,88 @@
+use bsdiff_rs::rust::{bsdiff_raw, RustBackend};
+use bsdiff_rs::{bsdiff43_vec, bspatch43_vec, jbsdiff40_vec, BsDiff, BsDiffError, OldIndex};
+use rand::Rng;
+
+fn generate_data(seed: u128, length: usize) -> Vec<u8> {
+    let mut rng = rand_pcg::Pcg64Mcg::new(seed);
+    (0..length).map(|_| rng.gen_range(0, 16)).collect()
+}
+
+fn old_and_new() -> (Vec<u8>, Vec<u8>) {
+    let old = generate_data(20, 20000);
+    let mut new = old.clone();
+    new[500..600].copy_from_slice(&generate_data(21, 100));
+    new.splice(9000..9000, generate_data(22, 300));
+    (old, new)
+}
+
+fn saved(index: &OldIndex) -> Vec<u8> {
+    let mut file = Vec::new();
+    index.write_to(&mut file).unwrap();
+    file
+}
+
+fn expect_error(result: Result<OldIndex, BsDiffError>, expected: &str) {
+    match result {
+        Err(err) if format!("{:?}", err) == expected => {}
+        Err(err) => panic!("Expected {}, got {:?}", expected, err),
+        Ok(_) => panic!("Expected {}, got an index", expected),
+    }
+}
+
+#[test]
+fn index_gives_identical_patches() {
+    let (old, new) = old_and_new();
+    let index = OldIndex::new(&old);
+
+    assert!(bsdiff43_vec(&index, &new).unwrap() == bsdiff43_vec(&old, &new).unwrap());
+    assert!(jbsdiff40_vec(&index, &new).unwrap() == jbsdiff40_vec(&old, &new).unwrap());
+    let (mut indexed, mut plain) = (Vec::new(), Vec::new());
+    bsdiff_raw(&index, &new, &mut indexed).unwrap();
+    bsdiff_raw(&old[..], &new, &mut plain).unwrap();
+    assert!(indexed == plain);
+
+    let patch = BsDiff::<RustBackend>::bsdiff43_vec(&index, &new).unwrap();
+    assert_eq!(bspatch43_vec(&old, &patch[..]).unwrap(), new);
+}
+
+#[test]
+fn saved_index_round_trips() {
+    let (old, new) = old_and_new();
+    let file = saved(&OldIndex::new(&old));
+    let loaded = OldIndex::read_from(&old, &file[..]).unwrap();
+    assert!(saved(&loaded) == file);
+    assert!(bsdiff43_vec(&loaded, &new).unwrap() == bsdiff43_vec(&old, &new).unwrap());
+
+    let empty = saved(&OldIndex::new(b""));
+    assert!(OldIndex::read_from(b"", &empty[..]).is_ok());
+}
+
+#[test]
+fn saved_index_rejects_other_data() {
+    let (old, _) = old_and_new();
+    let file = saved(&OldIndex::new(&old));
+    expect_error(OldIndex::read_from(&old[1..], &file[..]), "IndexMismatch");
+    let mut changed = old.clone();
+    changed[100] ^= 1;
+    expect_error(OldIndex::read_from(&changed, &file[..]), "IndexMismatch");
+}
+
+#[test]
+fn saved_index_rejects_damage() {
+    let (old, _) = old_and_new();
+    let file = saved(&OldIndex::new(&old));
+
+    let mut bad_magic = file.clone();
+    bad_magic[0] = b'X';
+    expect_error(OldIndex::read_from(&old, &bad_magic[..]), "BadMagic");
+    expect_error(OldIndex::read_from(&old, &file[..20]), "TruncatedHeader");
+    expect_error(OldIndex::read_from(&old, &file[..file.len() - 1]), "CorruptIndex");
+
+    let mut version = file.clone();
+    version[8] = 2;
+    expect_error(OldIndex::read_from(&old, &version[..]), "CorruptIndex");
+
+    let mut flipped = file.clone();
+    flipped[1000] ^= 0x10;
+    expect_error(OldIndex::read_from(&old, &flipped[..]), "CorruptIndex");
+}
@@ -1,5 +1,7 @@
 #![cfg(feature = "integration_test")]
-use bsdiff_rs::{bsdiff43, bspatch43, BsDiffResult};
+use bsdiff_rs::c::CBackend;
+use bsdiff_rs::rust::RustBackend;
+use bsdiff_rs::{bsdiff43, bspatch43, Backend, BsDiff, BsDiffResult};
 use rand::Rng;
 use rand_pcg::Pcg64Mcg;
 use std::ffi::OsStr;
@@ -85,7 +87,138 @@ mod bsdiff_c {
     }
 }
 
-#[cfg(not(feature = "c_backend"))]
+mod bsdiff_percival {
+    use super::Integration;
+    use bsdiff_rs::{bsdiff40, bspatch40, BsDiffResult};
+    use std::ffi::OsStr;
+    use std::io::{Read, Write};
+    use std::path::Path;
+    use std::process::Command;
+
+    const BSDIFF_PERCIVAL_EXECUTABLE_PATH: &str = "target/percival/bsdiff";
+    const BSPATCH_PERCIVAL_EXECUTABLE_PATH: &str = "target/percival/bspatch";
+    pub struct BsDiffPercival;
+
+    impl Integration for BsDiffPercival {
+        fn diff<S: AsRef<OsStr>>(old_file: S, new_file: S, patch_file: S) -> bool {
+            assert!(
+                Path::new(BSDIFF_PERCIVAL_EXECUTABLE_PATH).exists(),
+                "The stock bsdiff executable cannot be found. (Have you run test_setup.sh?)"
+            );
+            let patch_status = Command::new(BSDIFF_PERCIVAL_EXECUTABLE_PATH)
+                .arg(&old_file)
+                .arg(&new_file)
+                .arg(&patch_file)
+                .status()
+                .expect("Failed to run bsdiff");
+            patch_status.success()
+        }
+
+        fn patch<S: AsRef<OsStr>>(old_file: S, new_file: S, patch_file: S) -> bool {
+            assert!(
+                Path::new(BSPATCH_PERCIVAL_EXECUTABLE_PATH).exists(),
+                "The stock bspatch executable cannot be found. (Have you run test_setup.sh?)"
+            );
+            let patch_status = Command::new(BSPATCH_PERCIVAL_EXECUTABLE_PATH)
+                .arg(&old_file)
+                .arg(&new_file)
+                .arg(&patch_file)
+                .status()
+                .expect("Failed to run bspatch");
+            patch_status.success()
+        }
+
+        fn rust_diff<W: Write>(old: &[u8], new: &[u8], patch: W) -> BsDiffResult<()> {
+            bsdiff40(old, new, patch)
+        }
+
+        fn rust_patch<W: Write, R: Read>(old: &[u8], new: W, patch: R) -> BsDiffResult<()> {
+            bspatch40(old, new, patch)
+        }
+    }
+}
+
+mod bsdiff_android {
+    use super::Integration;
+    use bsdiff_rs::{bsdf2_diff, bsdf2_patch, Bsdf2Codecs, BsDiffResult, Codec};
+    use std::ffi::OsStr;
+    use std::io::{Read, Write};
+    use std::path::Path;
+    use std::process::Command;
+
+    const BSDIFF_ANDROID_EXECUTABLE_PATH: &str = "target/android/bsdiff";
+    const BSPATCH_ANDROID_EXECUTABLE_PATH: &str = "target/android/bspatch";
+
+    /// Android's bsdiff writing `BSDF2` with every stream compressed by `COMPRESSOR`
+    pub trait Compressor {
+        const COMPRESSOR: &'static str;
+        const CODEC: Codec;
+    }
+
+    pub struct Bz2;
+
+    impl Compressor for Bz2 {
+        const COMPRESSOR: &'static str = "bz2";
+        const CODEC: Codec = Codec::Bzip2;
+    }
+
+    #[cfg(feature = "brotli")]
+    pub struct Brotli;
+
+    #[cfg(feature = "brotli")]
+    impl Compressor for Brotli {
+        const COMPRESSOR: &'static str = "brotli";
+        const CODEC: Codec = Codec::Brotli(9);
+    }
+
+    pub struct BsDiffAndroid<C>(C);
+
+    impl<C: Compressor> Integration for BsDiffAndroid<C> {
+        fn diff<S: AsRef<OsStr>>(old_file: S, new_file: S, patch_file: S) -> bool {
+            assert!(
+                Path::new(BSDIFF_ANDROID_EXECUTABLE_PATH).exists(),
+                "The Android bsdiff executable cannot be found. (Have you run test_setup.sh?)"
+            );
+            let patch_status = Command::new(BSDIFF_ANDROID_EXECUTABLE_PATH)
+                .arg("--format=bsdf2")
+                .arg(format!("--type={}", C::COMPRESSOR))
+                .arg(&old_file)
+                .arg(&new_file)
+                .arg(&patch_file)
+                .status()
+                .expect("Failed to run bsdiff");
+            patch_status.success()
+        }
+
+        fn patch<S: AsRef<OsStr>>(old_file: S, new_file: S, patch_file: S) -> bool {
+            assert!(
+                Path::new(BSPATCH_ANDROID_EXECUTABLE_PATH).exists(),
+                "The Android bspatch executable cannot be found. (Have you run test_setup.sh?)"
+            );
+            let patch_status = Command::new(BSPATCH_ANDROID_EXECUTABLE_PATH)
+                .arg(&old_file)
+                .arg(&new_file)
+                .arg(&patch_file)
+                .status()
+                .expect("Failed to run bspatch");
+            patch_status.success()
+        }
+
+        fn rust_diff<W: Write>(old: &[u8], new: &[u8], patch: W) -> BsDiffResult<()> {
+            let codecs = Bsdf2Codecs {
+                ctrl: C::CODEC,
+                diff: C::CODEC,
+                extra: C::CODEC,
+            };
+            bsdf2_diff(old, new, patch, &codecs)
+        }
+
+        fn rust_patch<W: Write, R: Read>(old: &[u8], new: W, patch: R) -> BsDiffResult<()> {
+            bsdf2_patch(old, new, patch)
+        }
+    }
+}
+
 mod bsdiff_java {
     use super::Integration;
     use bsdiff_rs::{jbsdiff40, jbspatch40, BsDiffResult};
@@ -155,11 +288,34 @@ macro_rules! data_cases {
                 check_patch_eq::<bsdiff_c::BsDiffC>(&$old, &$new);
             }
 
-            #[cfg(not(feature = "c_backend"))]
+            #[test]
+            fn check_patch_eq_percival() {
+                check_patch_eq::<bsdiff_percival::BsDiffPercival>(&$old, &$new);
+            }
+
+            #[test]
+            fn check_patch_eq_android_bz2() {
+                check_patch_eq::<bsdiff_android::BsDiffAndroid<bsdiff_android::Bz2>>(&$old, &$new);
+            }
+
+            #[cfg(feature = "brotli")]
+            #[test]
+            fn check_patch_eq_android_brotli() {
+                check_patch_eq::<bsdiff_android::BsDiffAndroid<bsdiff_android::Brotli>>(
+                    &$old, &$new,
+                );
+            }
+
             #[test]
             fn check_patch_eq_java() {
                 check_patch_eq::<bsdiff_java::BsDiffJava>(&$old, &$new);
             }
+
+            #[test]
+            fn check_backend_interop_t() {
+                check_backend_interop::<RustBackend, CBackend>(&$old, &$new);
+                check_backend_interop::<CBackend, RustBackend>(&$old, &$new);
+            }
         }
     )*
     }
@@ -179,6 +335,12 @@ fn bsdiff_43_diff_patch(old: &[u8], new: &[u8]) {
     assert_eq!(output_1.as_slice(), new);
 }
 
+fn check_backend_interop<D: Backend, P: Backend>(old: &[u8], new: &[u8]) {
+    let patch = BsDiff::<D>::bsdiff43_vec(old, new).expect("Failed to diff");
+    let output = BsDiff::<P>::bspatch43_vec(old, &patch[..]).expect("Failed to patch");
+    assert_eq!(output.as_slice(), new);
+}
+
 fn check_patch_eq<I: Integration>(old: &[u8], new: &[u8]) {
     let work_dir = TempDir::new("bsdiff-testing").expect("Unable to create tempdir");
     let result = std::panic::catch_unwind(|| {
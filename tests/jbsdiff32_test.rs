@@ -0,0 +1,104 @@
+use bsdiff_rs::rust::{bsdiff_raw_32bit, bspatch_raw_32bit};
+use bsdiff_rs::{bsdiff40_vec, bspatch40_vec, jbsdiff40_32bit, jbspatch40_32bit};
+use bsdiff_rs::{BsDiffError, OldIndex};
+use bzip2::read::BzDecoder;
+use rand::Rng;
+use std::convert::TryInto;
+use std::io::Read;
+
+const MOVED_OLD: &[u8] = include_bytes!("fixtures/bsdiff40/moved.old");
+const MOVED_NEW: &[u8] = include_bytes!("fixtures/bsdiff40/moved.new");
+const MOVED_PATCH: &[u8] = include_bytes!("fixtures/bsdiff40/moved.patch");
+
+fn generate_data(seed: u128, length: usize) -> Vec<u8> {
+    let mut rng = rand_pcg::Pcg64Mcg::new(seed);
+    (0..length).map(|_| rng.gen_range(0, 16)).collect()
+}
+
+/// Blocks of `old` in reverse order with a few bytes changed, so most seeks go backwards
+fn reversed(old: &[u8]) -> Vec<u8> {
+    let mut new: Vec<u8> = old.chunks(5000).rev().flatten().copied().collect();
+    for i in (0..new.len()).step_by(997) {
+        new[i] ^= 0x55;
+    }
+    new
+}
+
+fn jbsdiff(old: &[u8], new: &[u8]) -> Vec<u8> {
+    let mut patch = Vec::new();
+    jbsdiff40_32bit(old, new, &mut patch).unwrap();
+    patch
+}
+
+fn jbspatch(old: &[u8], patch: &[u8]) -> Result<Vec<u8>, BsDiffError> {
+    let mut new = Vec::new();
+    jbspatch40_32bit(old, &mut new, patch)?;
+    Ok(new)
+}
+
+#[test]
+fn backward_seeks_round_trip() {
+    let old = generate_data(1, 60_000);
+    let new = reversed(&old);
+    assert!(jbspatch(&old, &jbsdiff(&old, &new)).unwrap() == new);
+
+    let mut patch = Vec::new();
+    bsdiff_raw_32bit(&OldIndex::new(&old), &new, &mut patch).unwrap();
+    let mut generated = vec![0; new.len()];
+    bspatch_raw_32bit(&old, &mut generated, &patch[..]).unwrap();
+    assert!(generated == new);
+}
+
+#[test]
+fn negative_seeks_keep_their_sign() {
+    let old = generate_data(3, 60_000);
+    let patch = jbsdiff(&old, &reversed(&old));
+    let ctrl_len = u64::from_le_bytes(patch[8..16].try_into().unwrap()) as usize;
+    let mut ctrl = Vec::new();
+    BzDecoder::new(&patch[32..32 + ctrl_len])
+        .read_to_end(&mut ctrl)
+        .unwrap();
+    let seeks: Vec<&[u8]> = ctrl.chunks(24).map(|entry| &entry[16..]).collect();
+    assert!(seeks
+        .iter()
+        .any(|seek| seek[7] == 0x80 && seek[..4] != [0; 4]));
+    assert!(seeks
+        .iter()
+        .all(|seek| seek[3] & 0x80 == 0 && seek[4..7] == [0; 3]));
+}
+
+#[test]
+fn interoperates_with_bsdiff40() {
+    // Below 2 GiB the 32-bit encoding is the sign-magnitude one of the original bsdiff
+    let old = generate_data(5, 60_000);
+    let new = reversed(&old);
+    let patch = jbsdiff(&old, &new);
+    assert!(patch == bsdiff40_vec(&old, &new).unwrap());
+    assert!(bspatch40_vec(&old, &patch[..]).unwrap() == new);
+    // Only a regression check: the fixture is not from stock bsdiff, see its README. The
+    // integration tests compare against stock bsdiff itself.
+    assert!(jbspatch(MOVED_OLD, MOVED_PATCH).unwrap() == MOVED_NEW);
+}
+
+#[test]
+fn values_beyond_32_bits_are_rejected() {
+    let old = [0u8; 16];
+    let mut ctrl = [0u8; 24];
+    ctrl[0] = 1;
+    for &high in &[[0, 0, 1, 0x80], [0, 0, 0, 0x40]] {
+        ctrl[20..].copy_from_slice(&high);
+        let patch = [&ctrl[..], &[7]].concat();
+        let mut new = [0u8; 1];
+        match bspatch_raw_32bit(&old, &mut new, &patch[..]) {
+            Err(BsDiffError::InvalidControl) => {}
+            other => panic!("{:?}", other),
+        }
+    }
+    ctrl[16..20].copy_from_slice(&0x8000_0000u32.to_le_bytes());
+    ctrl[20..].copy_from_slice(&[0; 4]);
+    let patch = [&ctrl[..], &[7]].concat();
+    match bspatch_raw_32bit(&old, &mut [0u8; 1], &patch[..]) {
+        Err(BsDiffError::InvalidControl) => {}
+        other => panic!("{:?}", other),
+    }
+}
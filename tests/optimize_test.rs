@@ -0,0 +1,97 @@
+use bsdiff_rs::{bsdiff43_optimize, bsdiff43_vec, bsdiff43_with, bspatch43_vec};
+use bsdiff_rs::{bsdiff_raw_optimize, bsdiff_raw_with, bspatch_raw};
+use bsdiff_rs::{jbsdiff40_optimize, jbsdiff40_vec, jbsdiff40_with, jbspatch40_vec};
+use bsdiff_rs::{DiffOptions, OldIndex};
+use rand::Rng;
+
+fn generate_data(seed: u128, length: usize) -> Vec<u8> {
+    let mut rng = rand_pcg::Pcg64Mcg::new(seed);
+    (0..length).map(|_| rng.gen_range(0, 16)).collect()
+}
+
+/// Pieces of `old` with some bytes changed, and some new data
+fn shuffle(old: &[u8], seed: u128, length: usize) -> Vec<u8> {
+    let mut rng = rand_pcg::Pcg64Mcg::new(seed);
+    let mut new = Vec::new();
+    while new.len() < length {
+        if rng.gen_range(0, 8) == 0 {
+            new.extend(generate_data(rng.gen(), rng.gen_range(1, 200)));
+            continue;
+        }
+        let start = rng.gen_range(0, old.len() - 2000);
+        let mut piece = old[start..start + rng.gen_range(1, 2000)].to_vec();
+        for byte in piece.iter_mut() {
+            if rng.gen_range(0, 30) == 0 {
+                *byte = rng.gen();
+            }
+        }
+        new.extend_from_slice(&piece);
+    }
+    new
+}
+
+/// A file needing a full diff, and one differing from `old` only in the middle
+fn pairs() -> Vec<(Vec<u8>, Vec<u8>)> {
+    let old = generate_data(1, 80_000);
+    let mut edited = old.clone();
+    edited.splice(40_000..41_000, shuffle(&old, 3, 2000));
+    vec![(old.clone(), shuffle(&old, 5, 90_000)), (old, edited)]
+}
+
+#[test]
+fn optimized_raw_patches_are_reproducible() {
+    for (old, new) in pairs() {
+        let mut patch = Vec::new();
+        let options = bsdiff_raw_optimize(&old, &new, &mut patch).unwrap();
+        let mut generated = vec![0; new.len()];
+        bspatch_raw(&old, &mut generated, &patch[..]).unwrap();
+        assert!(generated == new);
+
+        let mut again = Vec::new();
+        bsdiff_raw_with(&old, &new, &mut again, &options).unwrap();
+        assert!(again == patch, "{:?}", options);
+    }
+}
+
+#[test]
+fn optimized_bsdiff43_patches_are_smallest() {
+    for (old, new) in pairs() {
+        let mut patch = Vec::new();
+        let options = bsdiff43_optimize(&old, &new, &mut patch).unwrap();
+        assert!(bspatch43_vec(&old, &patch[..]).unwrap() == new);
+
+        let mut again = Vec::new();
+        bsdiff43_with(&old, &new, &mut again, &options).unwrap();
+        assert!(again == patch, "{:?}", options);
+
+        assert!(patch.len() <= bsdiff43_vec(&old, &new).unwrap().len());
+        let mut smallest = Vec::new();
+        bsdiff43_with(&old, &new, &mut smallest, &DiffOptions::smallest()).unwrap();
+        assert!(patch.len() <= smallest.len());
+    }
+}
+
+#[test]
+fn optimized_jbsdiff40_patches_are_smallest() {
+    for (old, new) in pairs() {
+        let mut patch = Vec::new();
+        let options = jbsdiff40_optimize(&old, &new, &mut patch).unwrap();
+        assert!(jbspatch40_vec(&old, &patch[..]).unwrap() == new);
+
+        let mut again = Vec::new();
+        jbsdiff40_with(&old, &new, &mut again, &options).unwrap();
+        assert!(again == patch, "{:?}", options);
+        assert!(patch.len() <= jbsdiff40_vec(&old, &new).unwrap().len());
+    }
+}
+
+#[test]
+fn optimize_accepts_an_index() {
+    let (old, new) = pairs().remove(0);
+    let index = OldIndex::new(&old);
+    let (mut indexed, mut plain) = (Vec::new(), Vec::new());
+    let options = bsdiff43_optimize(&index, &new, &mut indexed).unwrap();
+    assert!(!options.trim);
+    bsdiff43_optimize(&old, &new, &mut plain).unwrap();
+    assert!(indexed == plain);
+}
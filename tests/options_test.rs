@@ -0,0 +1,174 @@
+use bsdiff_rs::rust::{bsdiff_raw, bsdiff_raw_segmented};
+use bsdiff_rs::{bsdiff43_vec, bsdiff43_with, bsdiff_raw_with, bspatch43_vec, bspatch_raw};
+use bsdiff_rs::{
+    jbsdiff40_32bit_with, jbsdiff40_vec, jbsdiff40_with, jbspatch40, jbspatch40_32bit,
+};
+use bsdiff_rs::{BsDiffError, Codec, Compression, DiffOptions, OldIndex};
+use rand::Rng;
+
+fn generate_data(seed: u128, length: usize) -> Vec<u8> {
+    let mut rng = rand_pcg::Pcg64Mcg::new(seed);
+    (0..length).map(|_| rng.gen_range(0, 16)).collect()
+}
+
+/// An edited copy of `old`: changed, inserted, deleted and repeated runs
+fn edit(old: &[u8]) -> Vec<u8> {
+    let mut new = old.to_vec();
+    new[100..200].copy_from_slice(&generate_data(21, 100));
+    new.splice(5000..5000, generate_data(22, 700));
+    new.drain(20000..21000);
+    new.splice(30000..30000, vec![7; 3000]);
+    new.extend_from_slice(&old[40000..45000]);
+    new
+}
+
+fn option_sets() -> Vec<DiffOptions> {
+    vec![
+        DiffOptions::default(),
+        DiffOptions::fast(),
+        DiffOptions::smallest(),
+        DiffOptions {
+            min_gain: 0,
+            mismatch_penalty: 0,
+            reuse_len: 0,
+            candidates: 16,
+            lookahead: 4,
+            trim: false,
+            segments: 5,
+            compression: Compression::new(2),
+            codec: Codec::None,
+        },
+        DiffOptions {
+            min_gain: 100,
+            mismatch_penalty: 4,
+            reuse_len: usize::MAX,
+            ..DiffOptions::default()
+        },
+    ]
+}
+
+#[test]
+fn every_option_set_patches_correctly() {
+    let old = generate_data(20, 100_000);
+    // Large enough to trim, then too different to
+    for new in [edit(&old), generate_data(23, 50_000)] {
+        for options in option_sets() {
+            let mut patch = Vec::new();
+            bsdiff_raw_with(&old, &new, &mut patch, &options).unwrap();
+            let mut generated = vec![0; new.len()];
+            bspatch_raw(&old, &mut generated, &patch[..]).unwrap();
+            assert_eq!(generated, new, "{:?}", options);
+
+            let mut patch = Vec::new();
+            bsdiff43_with(&OldIndex::new(&old), &new, &mut patch, &options).unwrap();
+            assert_eq!(
+                bspatch43_vec(&old, &patch[..]).unwrap(),
+                new,
+                "{:?}",
+                options
+            );
+
+            let mut patch = Vec::new();
+            jbsdiff40_with(&old, &new, &mut patch, &options).unwrap();
+            let mut generated = Vec::new();
+            jbspatch40(&old, &mut generated, &patch[..]).unwrap();
+            assert_eq!(generated, new, "{:?}", options);
+
+            let mut patch = Vec::new();
+            jbsdiff40_32bit_with(&old, &new, &mut patch, &options).unwrap();
+            let mut generated = Vec::new();
+            jbspatch40_32bit(&old, &mut generated, &patch[..]).unwrap();
+            assert_eq!(generated, new, "{:?}", options);
+        }
+    }
+}
+
+#[test]
+fn default_options_match_plain_functions() {
+    let old = generate_data(20, 100_000);
+    let new = edit(&old);
+    let options = DiffOptions::default();
+
+    let (mut plain, mut with) = (Vec::new(), Vec::new());
+    bsdiff_raw(&old, &new, &mut plain).unwrap();
+    bsdiff_raw_with(&old, &new, &mut with, &options).unwrap();
+    assert!(plain == with);
+
+    let mut with = Vec::new();
+    bsdiff43_with(&old, &new, &mut with, &options).unwrap();
+    assert!(bsdiff43_vec(&old, &new).unwrap() == with);
+
+    let mut with = Vec::new();
+    jbsdiff40_with(&old, &new, &mut with, &options).unwrap();
+    assert!(jbsdiff40_vec(&old, &new).unwrap() == with);
+}
+
+#[test]
+fn segments_option_matches_segmented_diff() {
+    let old = generate_data(20, 100_000);
+    let new = edit(&old);
+    let (mut segmented, mut with) = (Vec::new(), Vec::new());
+    bsdiff_raw_segmented(&old, &new, &mut segmented, 4).unwrap();
+    let options = DiffOptions {
+        segments: 4,
+        ..DiffOptions::default()
+    };
+    bsdiff_raw_with(&old, &new, &mut with, &options).unwrap();
+    assert!(segmented == with);
+}
+
+#[test]
+fn trim_can_be_disabled() {
+    let old = generate_data(20, 100_000);
+    let mut new = old.clone();
+    new[70000..70040].copy_from_slice(&generate_data(24, 40));
+    let options = DiffOptions {
+        trim: false,
+        ..DiffOptions::default()
+    };
+    let (mut untrimmed, mut indexed) = (Vec::new(), Vec::new());
+    bsdiff_raw_with(&old, &new, &mut untrimmed, &options).unwrap();
+    bsdiff_raw(&OldIndex::new(&old), &new, &mut indexed).unwrap();
+    assert!(untrimmed == indexed);
+}
+
+#[test]
+fn levels_set_the_block_size() {
+    let old = generate_data(20, 100_000);
+    let new = edit(&old);
+    for level in 1..=9 {
+        let options = DiffOptions {
+            compression: Compression::new(level),
+            ..DiffOptions::default()
+        };
+        let mut patch = Vec::new();
+        bsdiff43_with(&old, &new, &mut patch, &options).unwrap();
+        // bzip2 records the block size in hundreds of kilobytes after its magic number
+        assert_eq!(&patch[24..28], format!("BZh{}", level).as_bytes());
+        assert!(bspatch43_vec(&old, &patch[..]).unwrap() == new);
+
+        let mut patch = Vec::new();
+        jbsdiff40_with(&old, &new, &mut patch, &options).unwrap();
+        assert_eq!(&patch[32..36], format!("BZh{}", level).as_bytes());
+        let mut generated = Vec::new();
+        jbspatch40(&old, &mut generated, &patch[..]).unwrap();
+        assert!(generated == new);
+    }
+}
+
+#[test]
+fn levels_out_of_range_are_rejected() {
+    let old = generate_data(20, 1000);
+    for &level in &[0, 10] {
+        let options = DiffOptions {
+            compression: Compression::new(level),
+            ..DiffOptions::default()
+        };
+        let mut patch = Vec::new();
+        let err = bsdiff43_with(&old, &old, &mut patch, &options).unwrap_err();
+        assert!(matches!(err, BsDiffError::InvalidOptions(_)), "{:?}", err);
+        let err = jbsdiff40_with(&old, &old, &mut patch, &options).unwrap_err();
+        assert!(matches!(err, BsDiffError::InvalidOptions(_)), "{:?}", err);
+        assert!(patch.is_empty());
+    }
+}
@@ -0,0 +1,234 @@
+use bsdiff_rs::{jbsdiff40_seekable, jbsdiff40_seekable_in, jbsdiff40_seekable_with};
+use bsdiff_rs::{jbsdiff40_vec, jbsdiff40_with};
+use bsdiff_rs::{jbspatch40_seekable, jbspatch40_seekable_with, jbspatch40_with};
+use bsdiff_rs::{BsDiffError, Compression, DiffOptions, PatchLimits, PatchOptions};
+use rand::Rng;
+use std::io::{self, Cursor, Read, Seek, SeekFrom, Write};
+use tempdir::TempDir;
+
+fn generate_data(seed: u128, length: usize) -> Vec<u8> {
+    let mut rng = rand_pcg::Pcg64Mcg::new(seed);
+    (0..length).map(|_| rng.gen()).collect()
+}
+
+/// Pieces of `old` in a different order, with changed bytes and new data in between
+fn shuffle(old: &[u8], seed: u128, length: usize) -> Vec<u8> {
+    let mut rng = rand_pcg::Pcg64Mcg::new(seed);
+    let mut new = Vec::new();
+    while new.len() < length {
+        if rng.gen_range(0, 4) == 0 {
+            new.extend(generate_data(rng.gen(), rng.gen_range(1, 5000)));
+            continue;
+        }
+        let start = rng.gen_range(0, old.len() - 5000);
+        let mut piece = old[start..start + rng.gen_range(1, 5000)].to_vec();
+        for byte in piece.iter_mut() {
+            if rng.gen_range(0, 50) == 0 {
+                *byte = rng.gen();
+            }
+        }
+        new.extend_from_slice(&piece);
+    }
+    new
+}
+
+/// Records the largest single read, to show the patch is never read whole
+struct Tracked<R> {
+    inner: R,
+    largest_read: usize,
+}
+
+impl<R: Read> Read for Tracked<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read = self.inner.read(buf)?;
+        self.largest_read = self.largest_read.max(read);
+        Ok(read)
+    }
+}
+
+impl<R: Seek> Seek for Tracked<R> {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        self.inner.seek(pos)
+    }
+}
+
+fn seekable(old: &[u8], patch: &[u8]) -> Result<Vec<u8>, BsDiffError> {
+    let mut new = Vec::new();
+    jbspatch40_seekable(old, &mut new, Cursor::new(patch))?;
+    Ok(new)
+}
+
+#[test]
+fn matches_jbspatch40() {
+    let old = generate_data(1, 200_000);
+    for &(seed, length) in &[(3, 250_000), (5, 10), (7, 0)] {
+        let new = shuffle(&old, seed, length);
+        let patch = jbsdiff40_vec(&old, &new).unwrap();
+        assert!(seekable(&old, &patch).unwrap() == new);
+    }
+}
+
+#[test]
+fn reads_from_the_current_position() {
+    let old = generate_data(9, 100_000);
+    let new = shuffle(&old, 11, 100_000);
+    let file = [&b"leading bytes"[..], &jbsdiff40_vec(&old, &new).unwrap()].concat();
+    let mut patch = Cursor::new(&file[..]);
+    patch.seek(SeekFrom::Start(13)).unwrap();
+    let mut generated = Vec::new();
+    jbspatch40_seekable(&old, &mut generated, patch).unwrap();
+    assert!(generated == new);
+}
+
+#[test]
+fn patch_is_not_buffered() {
+    let old = generate_data(13, 500_000);
+    let new = shuffle(&old, 15, 2_000_000);
+    let patch = jbsdiff40_vec(&old, &new).unwrap();
+    assert!(patch.len() > 400_000);
+
+    let options = PatchOptions {
+        limits: PatchLimits {
+            max_memory: 256 * 1024,
+            ..PatchLimits::default()
+        },
+        ..PatchOptions::default()
+    };
+    match jbspatch40_with(&old, Vec::new(), &patch[..], &options) {
+        Err(BsDiffError::LimitExceeded { .. }) => {}
+        other => panic!("{:?}", other),
+    }
+
+    let mut tracked = Tracked {
+        inner: Cursor::new(&patch[..]),
+        largest_read: 0,
+    };
+    let mut generated = Vec::new();
+    jbspatch40_seekable_with(&old, &mut generated, &mut tracked, &options).unwrap();
+    assert!(generated == new);
+    assert!(tracked.largest_read <= 64 * 1024);
+}
+
+#[test]
+fn damaged_patches_are_rejected() {
+    let old = generate_data(17, 50_000);
+    let new = shuffle(&old, 19, 50_000);
+    let patch = jbsdiff40_vec(&old, &new).unwrap();
+
+    match seekable(&old, &patch[..patch.len() / 2]) {
+        Err(BsDiffError::CorruptStream(_)) => {}
+        other => panic!("{:?}", other.map(|new| new.len())),
+    }
+    match seekable(&old, &patch[..20]) {
+        Err(BsDiffError::TruncatedHeader) => {}
+        other => panic!("{:?}", other.map(|new| new.len())),
+    }
+    let mut bad = patch.clone();
+    bad[0] = b'X';
+    match seekable(&old, &bad) {
+        Err(BsDiffError::BadMagic) => {}
+        other => panic!("{:?}", other.map(|new| new.len())),
+    }
+}
+
+/// A sink that runs out of space after `capacity` bytes
+struct Full {
+    inner: Cursor<Vec<u8>>,
+    capacity: u64,
+}
+
+impl Write for Full {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let room = self.capacity.saturating_sub(self.inner.position()) as usize;
+        self.inner.write(&buf[..buf.len().min(room)])
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Seek for Full {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        self.inner.seek(pos)
+    }
+}
+
+fn spools() -> usize {
+    let prefix = format!("bsdiff-rs-{}-", std::process::id());
+    let entries = std::fs::read_dir(std::env::temp_dir()).unwrap();
+    entries
+        .filter(|entry| {
+            let name = entry.as_ref().unwrap().file_name();
+            name.to_string_lossy().starts_with(&prefix)
+        })
+        .count()
+}
+
+// The only test writing patches this way, so no other test's spools are counted
+#[test]
+fn seekable_writer_matches_jbsdiff40() {
+    let old = generate_data(21, 200_000);
+    for &(seed, length) in &[(23, 250_000), (25, 10), (27, 0)] {
+        let new = shuffle(&old, seed, length);
+        let mut patch = Cursor::new(Vec::new());
+        jbsdiff40_seekable(&old, &new, &mut patch).unwrap();
+        assert!(patch.into_inner() == jbsdiff40_vec(&old, &new).unwrap());
+    }
+
+    // From the current position, leaving the writer at the end of the patch
+    let new = shuffle(&old, 29, 100_000);
+    let options = DiffOptions {
+        compression: Compression::new(3),
+        ..DiffOptions::smallest()
+    };
+    let mut patch = Cursor::new(b"leading bytes".to_vec());
+    patch.seek(SeekFrom::End(0)).unwrap();
+    jbsdiff40_seekable_with(&old, &new, &mut patch, &options).unwrap();
+    let mut expected = b"leading bytes".to_vec();
+    jbsdiff40_with(&old, &new, &mut expected, &options).unwrap();
+    assert_eq!(patch.position(), expected.len() as u64);
+    assert!(patch.into_inner() == expected);
+
+    let invalid = DiffOptions {
+        compression: Compression::new(0),
+        ..DiffOptions::default()
+    };
+    let mut patch = Cursor::new(Vec::new());
+    match jbsdiff40_seekable_with(&old, &new, &mut patch, &invalid) {
+        Err(BsDiffError::InvalidOptions(_)) => {}
+        other => panic!("{:?}", other),
+    }
+    assert!(patch.into_inner().is_empty());
+
+    // A failed write leaves the placeholder header behind, but no temporary files
+    let mut full = Full {
+        inner: Cursor::new(Vec::new()),
+        capacity: 100,
+    };
+    match jbsdiff40_seekable(&old, &new, &mut full) {
+        Err(BsDiffError::Io(_)) => {}
+        other => panic!("{:?}", other),
+    }
+    let partial = full.inner.into_inner();
+    assert!(partial.len() == 100 && partial[..32] == [0; 32]);
+    assert_eq!(spools(), 0);
+}
+
+#[test]
+fn spools_to_the_given_directory() {
+    let old = generate_data(31, 100_000);
+    let new = shuffle(&old, 33, 100_000);
+    let dir = TempDir::new("bsdiff-spool").unwrap();
+    let mut patch = Cursor::new(Vec::new());
+    jbsdiff40_seekable_in(&old, &new, &mut patch, &DiffOptions::default(), dir.path()).unwrap();
+    assert!(patch.into_inner() == jbsdiff40_vec(&old, &new).unwrap());
+    assert_eq!(std::fs::read_dir(dir.path()).unwrap().count(), 0);
+
+    let missing = dir.path().join("missing");
+    let mut patch = Cursor::new(Vec::new());
+    match jbsdiff40_seekable_in(&old, &new, &mut patch, &DiffOptions::default(), &missing) {
+        Err(BsDiffError::Io(_)) => {}
+        other => panic!("{:?}", other),
+    }
+}
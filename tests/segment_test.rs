@@ -0,0 +1,81 @@
+use bsdiff_rs::rust::{bsdiff_raw, bsdiff_raw_segmented};
+use bsdiff_rs::{bspatch_raw_with, PatchOptions};
+use bzip2::write::BzEncoder;
+use bzip2::Compression;
+use rand::Rng;
+use std::io::Write;
+
+fn generate_data(seed: u128, length: usize) -> Vec<u8> {
+    let mut rng = rand_pcg::Pcg64Mcg::new(seed);
+    (0..length).map(|_| rng.gen_range(0, 16)).collect()
+}
+
+/// An edited copy of `old`: a few changed, inserted and deleted runs
+fn edit(old: &[u8]) -> Vec<u8> {
+    let mut new = old.to_vec();
+    new[100..200].copy_from_slice(&generate_data(11, 100));
+    new.splice(5000..5000, generate_data(12, 700));
+    new.drain(20000..21000);
+    let len = new.len();
+    new[len - 300..].copy_from_slice(&generate_data(13, 300));
+    new
+}
+
+fn segmented_patch(old: &[u8], new: &[u8], segments: usize) -> Vec<u8> {
+    let mut patch = Vec::new();
+    bsdiff_raw_segmented(old, new, &mut patch, segments).expect("Failed to diff");
+    let mut generated = vec![0; new.len()];
+    bspatch_raw_with(old, &mut generated, &patch[..], &PatchOptions::strict())
+        .expect("Failed to patch");
+    assert_eq!(generated, new);
+    patch
+}
+
+#[test]
+fn segments_patch_correctly() {
+    let old = generate_data(10, 50000);
+    let new = edit(&old);
+    for &segments in &[2, 3, 7, 16, 1000] {
+        segmented_patch(&old, &new, segments);
+    }
+    segmented_patch(b"", &new, 4);
+    segmented_patch(&old, b"", 4);
+    segmented_patch(&old, &new[..3], 8);
+}
+
+#[test]
+fn one_segment_is_unchanged() {
+    let old = generate_data(10, 50000);
+    let new = edit(&old);
+    let mut expected = Vec::new();
+    bsdiff_raw(&old, &new, &mut expected).unwrap();
+    assert!(segmented_patch(&old, &new, 1) == expected);
+}
+
+fn compressed_len(raw: &[u8]) -> usize {
+    let mut encoder = BzEncoder::new(Vec::new(), Compression::best());
+    encoder.write_all(raw).unwrap();
+    encoder.finish().unwrap().len()
+}
+
+#[test]
+fn segments_cost_little() {
+    let old = generate_data(10, 50000);
+    let new = edit(&old);
+    let whole = compressed_len(&segmented_patch(&old, &new, 1));
+    let split = compressed_len(&segmented_patch(&old, &new, 8));
+    // Each boundary costs a control entry and a rediscovered match, not a re-sent segment
+    assert!(split <= whole + 8 * 32, "{} segmented vs {} whole", split, whole);
+}
+
+#[cfg(feature = "parallel")]
+#[test]
+fn segments_are_deterministic() {
+    let old = generate_data(10, 50000);
+    let new = edit(&old);
+    let expected = segmented_patch(&old, &new, 8);
+    let pool = rayon::ThreadPoolBuilder::new().num_threads(4).build().unwrap();
+    for _ in 0..4 {
+        assert!(pool.install(|| segmented_patch(&old, &new, 8)) == expected);
+    }
+}
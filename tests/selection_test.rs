@@ -0,0 +1,114 @@
+use bsdiff_rs::rust::{bsdiff_raw_with, bspatch_raw};
+use bsdiff_rs::{DiffOptions, OldIndex, SuffixIndex};
+use rand::Rng;
+
+fn generate_data(seed: u128, length: usize) -> Vec<u8> {
+    let mut rng = rand_pcg::Pcg64Mcg::new(seed);
+    (0..length).map(|_| rng.gen()).collect()
+}
+
+fn raw_patch(old: &[u8], new: &[u8], options: &DiffOptions) -> Vec<u8> {
+    let mut patch = Vec::new();
+    // Without trimming, so the whole of `new` is matched
+    bsdiff_raw_with(&OldIndex::new(old), new, &mut patch, options).unwrap();
+    let mut generated = vec![0; new.len()];
+    bspatch_raw(old, &mut generated, &patch[..]).unwrap();
+    assert!(generated == new);
+    patch
+}
+
+fn zeros(patch: &[u8]) -> usize {
+    patch.iter().filter(|&&byte| byte == 0).count()
+}
+
+#[test]
+fn candidates_prefer_matching_context() {
+    let marker = generate_data(1, 32);
+    let (first, second) = (generate_data(3, 400), generate_data(5, 400));
+    let old = [
+        &generate_data(7, 1000)[..],
+        &marker,
+        &first,
+        &generate_data(9, 1000),
+        &marker,
+        &second,
+    ]
+    .concat();
+    let (first_marker, second_marker) = (1000, 2432);
+
+    // The marker followed by one of the two contexts, off by a byte every 8, too often to
+    // find the context by searching
+    let followed_by = |context: &[u8]| {
+        let mut tail = [&marker[..], context].concat();
+        for i in (32..tail.len()).step_by(8) {
+            tail[i] ^= 0x80;
+        }
+        tail
+    };
+    // Followed by the context of whichever marker the search does not find
+    let index = SuffixIndex::new(&old);
+    let new = [(&second, first_marker), (&first, second_marker)]
+        .iter()
+        .map(|&(context, other)| {
+            let new = [&generate_data(11, 500)[..], &followed_by(context)].concat();
+            (new, other)
+        })
+        .find(|(new, other)| index.longest_match(&new[500..]) == (*other, 32))
+        .expect("Both markers matched their contexts")
+        .0;
+
+    let plain = raw_patch(&old, &new, &DiffOptions::default());
+    let options = DiffOptions {
+        candidates: 1,
+        ..DiffOptions::default()
+    };
+    // The context is copied with differences instead of stored as extra bytes
+    assert!(zeros(&raw_patch(&old, &new, &options)) > zeros(&plain) + 300);
+}
+
+#[test]
+fn lookahead_skips_short_detours() {
+    let long = generate_data(13, 2000);
+    let short = [&[11u8, 12][..], &long[..10], &generate_data(15, 100)].concat();
+    let old = [
+        &generate_data(17, 1000)[..],
+        &short,
+        &generate_data(19, 1000),
+        &long,
+    ]
+    .concat();
+    let new = [&generate_data(21, 500)[..], &[11, 12], &long].concat();
+
+    let plain = raw_patch(&old, &new, &DiffOptions::default());
+    let options = DiffOptions {
+        lookahead: 4,
+        ..DiffOptions::default()
+    };
+    // One control entry fewer, as the short match is never switched to
+    assert_eq!(raw_patch(&old, &new, &options).len() + 24, plain.len());
+}
+
+#[test]
+fn selection_keeps_patches_valid() {
+    let old = generate_data(23, 50_000);
+    let mut rng = rand_pcg::Pcg64Mcg::new(25);
+    let mut new = Vec::new();
+    while new.len() < 60_000 {
+        let start = rng.gen_range(0, old.len() - 1000);
+        let mut piece = old[start..start + rng.gen_range(1, 1000)].to_vec();
+        for byte in piece.iter_mut() {
+            if rng.gen_range(0, 20) == 0 {
+                *byte = rng.gen();
+            }
+        }
+        new.extend_from_slice(&piece);
+    }
+    for &(candidates, lookahead) in &[(1, 0), (0, 1), (16, 4), (1000, 64)] {
+        let options = DiffOptions {
+            candidates,
+            lookahead,
+            ..DiffOptions::default()
+        };
+        raw_patch(&old, &new, &options);
+    }
+}
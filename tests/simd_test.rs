@@ -0,0 +1,148 @@
+use bsdiff_rs::rust::simd::{matchlen, matchlen_back, Kernel};
+use rand::Rng;
+
+/// Pairs of mostly equal byte strings, so scores swing both ways
+fn generate_pair(seed: u128, length: usize) -> (Vec<u8>, Vec<u8>) {
+    let mut rng = rand_pcg::Pcg64Mcg::new(seed);
+    let a: Vec<u8> = (0..length).map(|_| rng.gen_range(0, 4)).collect();
+    let b = a
+        .iter()
+        .map(|&x| {
+            if rng.gen_range(0, 3) == 0 {
+                rng.gen()
+            } else {
+                x
+            }
+        })
+        .collect();
+    (a, b)
+}
+
+fn lengths() -> impl Iterator<Item = usize> {
+    (0..140).chain([255, 256, 257, 1000, 4099].iter().cloned())
+}
+
+/// The scoring loops as bsdiff wrote them: the first longest prefix with the highest positive sum
+fn best_prefix(scores: impl Iterator<Item = i64>) -> usize {
+    let (mut s, mut best, mut best_len) = (0, 0, 0);
+    for (i, score) in scores.enumerate() {
+        s += score;
+        if s > best {
+            best = s;
+            best_len = i + 1;
+        }
+    }
+    best_len
+}
+
+fn score(a: u8, b: u8, penalty: i64) -> i64 {
+    if a == b {
+        1
+    } else {
+        -penalty
+    }
+}
+
+#[test]
+fn matchlen_matches_scalar() {
+    for (seed, len) in lengths().enumerate() {
+        let (a, b) = generate_pair(seed as u128, len);
+        for start in 0..len.min(20) {
+            let expected = a[start..]
+                .iter()
+                .zip(&b[start..])
+                .take_while(|(x, y)| x == y)
+                .count();
+            assert_eq!(matchlen(&a[start..], &b[start..]), expected);
+            assert_eq!(matchlen(&a[start..], &a[start..]), len - start);
+
+            let end = len - start;
+            let expected = a[..end]
+                .iter()
+                .rev()
+                .zip(b[..end].iter().rev())
+                .take_while(|(x, y)| x == y)
+                .count();
+            assert_eq!(matchlen_back(&a[..end], &b[..end]), expected);
+            assert_eq!(
+                matchlen_back(&a[..end], &b[..len]),
+                matchlen_back(&a[..end], &b[start..])
+            );
+        }
+    }
+}
+
+#[test]
+fn kernels_match_scalar() {
+    for kernel in Kernel::available() {
+        for (seed, len) in lengths().enumerate() {
+            let (a, b) = generate_pair(100 + seed as u128, len);
+            let (c, d) = generate_pair(1000 + seed as u128, len);
+            let equal = a.iter().zip(&b).filter(|(x, y)| x == y).count();
+            assert_eq!(kernel.count_equal(&a, &b), equal, "{:?} {}", kernel, len);
+            assert_eq!(
+                kernel.count_equal(&a, &b[..len / 2]),
+                equal_prefix(&a, &b, len / 2)
+            );
+
+            for penalty in 1..4 {
+                let forward =
+                    best_prefix(a.iter().zip(&b).map(|(&x, &y)| score(x, y, penalty as i64)));
+                assert_eq!(
+                    kernel.best_forward(&a, &b, penalty),
+                    forward,
+                    "{:?} {} {}",
+                    kernel,
+                    len,
+                    penalty
+                );
+
+                let backward = best_prefix(
+                    a.iter()
+                        .rev()
+                        .zip(b.iter().rev())
+                        .map(|(&x, &y)| score(x, y, penalty as i64)),
+                );
+                assert_eq!(
+                    kernel.best_backward(&a, &b, penalty),
+                    backward,
+                    "{:?} {} {}",
+                    kernel,
+                    len,
+                    penalty
+                );
+            }
+
+            let overlap =
+                best_prefix((0..len).map(|i| (a[i] == b[i]) as i64 - (c[i] == d[i]) as i64));
+            assert_eq!(
+                kernel.best_overlap(&a, &b, &c, &d),
+                overlap,
+                "{:?} {}",
+                kernel,
+                len
+            );
+        }
+    }
+}
+
+fn equal_prefix(a: &[u8], b: &[u8], len: usize) -> usize {
+    a[..len]
+        .iter()
+        .zip(&b[..len])
+        .filter(|(x, y)| x == y)
+        .count()
+}
+
+#[test]
+fn add_bytes_wraps() {
+    for kernel in Kernel::available() {
+        for (seed, len) in lengths().enumerate() {
+            let (mut a, b) = generate_pair(200 + seed as u128, len);
+            let b: Vec<u8> = b.iter().map(|x| x.wrapping_mul(97)).collect();
+            let expected: Vec<u8> = a.iter().zip(&b).map(|(x, y)| x.wrapping_add(*y)).collect();
+            kernel.add_bytes(&mut a, &b);
+            assert_eq!(a, expected, "{:?} {}", kernel, len);
+        }
+    }
+}
@@ -0,0 +1,106 @@
+use bsdiff_rs::SuffixIndex;
+use rand::Rng;
+
+fn generate_data(seed: u128, length: usize, alphabet: u8) -> Vec<u8> {
+    let mut rng = rand_pcg::Pcg64Mcg::new(seed);
+    (0..length).map(|_| rng.gen_range(0, alphabet)).collect()
+}
+
+fn common_prefix(a: &[u8], b: &[u8]) -> usize {
+    a.iter().zip(b).take_while(|(x, y)| x == y).count()
+}
+
+#[test]
+fn find_all_matches_naive() {
+    let text = generate_data(30, 3000, 3);
+    let index = SuffixIndex::new(&text);
+    for len in 1..8 {
+        for start in (0..text.len() - len).step_by(97) {
+            let pattern = &text[start..start + len];
+            let expected: Vec<usize> = (0..=text.len() - len)
+                .filter(|&i| &text[i..i + len] == pattern)
+                .collect();
+            assert_eq!(index.find_all(pattern), expected);
+        }
+    }
+    assert_eq!(index.find_all(&[7]), Vec::<usize>::new());
+    assert_eq!(index.find_all(&text), vec![0]);
+    let past_end = [&text[text.len() - 2..], &[0u8][..]].concat();
+    assert!(!index.find_all(&past_end).contains(&(text.len() - 2)));
+}
+
+#[test]
+fn longest_match_is_longest() {
+    let text = generate_data(31, 5000, 4);
+    let index = SuffixIndex::new(&text);
+    for seed in 0..50 {
+        let pattern = generate_data(seed, 40, 4);
+        let (pos, len) = index.longest_match(&pattern);
+        assert_eq!(common_prefix(&text[pos..], &pattern), len);
+        let best = (0..text.len())
+            .map(|i| common_prefix(&text[i..], &pattern))
+            .max()
+            .unwrap();
+        assert_eq!(len, best);
+    }
+    assert_eq!(SuffixIndex::new(b"").longest_match(b"abc"), (0, 0));
+}
+
+#[test]
+fn lcp_matches_naive() {
+    for &(seed, alphabet) in &[(32, 2), (33, 255)] {
+        let text = generate_data(seed, 2000, alphabet);
+        let index = SuffixIndex::new(&text);
+        assert_eq!(index.lcp(1), None);
+
+        let index = index.with_lcp();
+        assert_eq!(index.lcp(0), Some(0));
+        for rank in 1..=text.len() {
+            let a = &text[index.suffix(rank - 1)..];
+            let b = &text[index.suffix(rank)..];
+            assert!(a < b);
+            assert_eq!(index.lcp(rank), Some(common_prefix(a, b)));
+        }
+    }
+}
+
+/// The plain binary search bsdiff has always used, comparing whole slices at every step
+fn reference_search(index: &SuffixIndex, new: &[u8], start: usize, end: usize) -> (usize, usize) {
+    let old = index.text();
+    if end - start < 2 {
+        let x = common_prefix(&old[index.suffix(start)..], new);
+        let y = common_prefix(&old[index.suffix(end)..], new);
+        if x > y {
+            (index.suffix(start), x)
+        } else {
+            (index.suffix(end), y)
+        }
+    } else {
+        let middle = start + (end - start) / 2;
+        let suffix = index.suffix(middle);
+        if old[suffix..] < *new {
+            reference_search(index, new, middle, end)
+        } else {
+            reference_search(index, new, start, middle)
+        }
+    }
+}
+
+#[test]
+fn longest_match_is_the_reference_match() {
+    for &(seed, length, alphabet) in &[(34, 3000, 2), (35, 70000, 4), (36, 70000, 255)] {
+        let text = generate_data(seed, length, alphabet);
+        let index = SuffixIndex::new(&text);
+        let mut patterns: Vec<Vec<u8>> = (0..300).map(|i| generate_data(i, 30, alphabet)).collect();
+        // Patterns taken from the text match past the cached prefixes
+        patterns.extend((0..300).map(|i| text[i * 7..i * 7 + 40].to_vec()));
+        patterns.push(Vec::new());
+        patterns.push(text[text.len() - 3..].to_vec());
+        for pattern in &patterns {
+            assert_eq!(
+                index.longest_match(pattern),
+                reference_search(&index, pattern, 0, text.len())
+            );
+        }
+    }
+}
@@ -0,0 +1,77 @@
+#[cfg(feature = "parallel")]
+use bsdiff_rs::rust::bench::par_sufsort;
+use bsdiff_rs::rust::bench::{qsufsort, sais, sais_64};
+use rand::Rng;
+
+fn generate_data(seed: u128, length: usize, alphabet: u8) -> Vec<u8> {
+    let mut rng = rand_pcg::Pcg64Mcg::new(seed);
+    (0..length).map(|_| rng.gen_range(0, alphabet)).collect()
+}
+
+fn check_same_order(old: &[u8]) {
+    let expected = qsufsort(old);
+    let narrow: Vec<isize> = sais(old).into_iter().map(|i| i as isize).collect();
+    assert_eq!(narrow, expected);
+    let wide: Vec<isize> = sais_64(old).into_iter().map(|i| i as isize).collect();
+    assert_eq!(wide, expected);
+    #[cfg(feature = "parallel")]
+    {
+        let parallel: Vec<isize> = par_sufsort(old).into_iter().map(|i| i as isize).collect();
+        assert_eq!(parallel, expected);
+    }
+}
+
+#[test]
+fn small_inputs() {
+    check_same_order(b"");
+    check_same_order(b"a");
+    check_same_order(b"ab");
+    check_same_order(b"ba");
+    check_same_order(b"banana");
+    check_same_order(b"mississippi");
+    check_same_order(&[255, 0, 255, 0, 255]);
+}
+
+#[test]
+fn random_inputs() {
+    for seed in 0..20 {
+        check_same_order(&generate_data(seed, 5000, 255));
+        check_same_order(&generate_data(seed, 5000, 4));
+        check_same_order(&generate_data(seed, 37, 2));
+    }
+}
+
+#[test]
+fn repetitive_inputs() {
+    check_same_order(&[0u8; 10000]);
+    check_same_order(&[255u8; 10000]);
+    let record = generate_data(7, 61, 255);
+    let repeated: Vec<u8> = record.iter().cycle().take(20000).cloned().collect();
+    check_same_order(&repeated);
+    let pattern: Vec<u8> = (0..20000u32).map(|i| (i.count_ones() % 3) as u8).collect();
+    check_same_order(&pattern);
+
+    // Fibonacci words recurse as deep as SA-IS can go
+    let (mut a, mut b) = (b"a".to_vec(), b"ab".to_vec());
+    while b.len() < 20000 {
+        let next = [&b[..], &a[..]].concat();
+        a = b;
+        b = next;
+    }
+    check_same_order(&b);
+}
+
+#[cfg(feature = "parallel")]
+#[test]
+fn parallel_patches_are_identical() {
+    let old = generate_data(3, 300_000, 16);
+    let mut new = old.clone();
+    new[1000..2000].copy_from_slice(&generate_data(4, 1000, 255));
+    new.extend_from_slice(&generate_data(5, 5000, 255));
+
+    let serial = rayon::ThreadPoolBuilder::new().num_threads(1).build().unwrap();
+    let parallel = rayon::ThreadPoolBuilder::new().num_threads(4).build().unwrap();
+    let expected = serial.install(|| bsdiff_rs::bsdiff43_vec(&old, &new).unwrap());
+    let actual = parallel.install(|| bsdiff_rs::bsdiff43_vec(&old, &new).unwrap());
+    assert!(expected == actual);
+}
@@ -0,0 +1,93 @@
+use bsdiff_rs::rust::{bsdiff_raw, bsdiff_raw_segmented};
+use bsdiff_rs::{bsdiff43_vec, bspatch43_vec, bspatch_raw_with, jbsdiff40_vec, jbspatch40};
+use bsdiff_rs::{OldIndex, PatchOptions};
+use rand::Rng;
+
+fn generate_data(seed: u128, length: usize) -> Vec<u8> {
+    let mut rng = rand_pcg::Pcg64Mcg::new(seed);
+    (0..length).map(|_| rng.gen_range(0, 16)).collect()
+}
+
+fn raw_patch(old: &[u8], new: &[u8]) -> Vec<u8> {
+    let mut patch = Vec::new();
+    bsdiff_raw(old, new, &mut patch).expect("Failed to diff");
+    let mut generated = vec![0; new.len()];
+    bspatch_raw_with(old, &mut generated, &patch[..], &PatchOptions::strict())
+        .expect("Failed to patch");
+    assert_eq!(generated, new);
+    patch
+}
+
+/// Files sharing long ends with the old one, each changed in a different way
+fn edits(old: &[u8]) -> Vec<Vec<u8>> {
+    let appended = [old, &generate_data(41, 3000)[..]].concat();
+    let mut edited = old.to_vec();
+    edited[70000..70040].copy_from_slice(&generate_data(42, 40));
+    let mut inserted = old.to_vec();
+    inserted.splice(1000..1000, generate_data(43, 500));
+    let mut deleted = old.to_vec();
+    deleted.drain(90000..95000);
+    vec![
+        appended,
+        edited,
+        inserted,
+        deleted,
+        old[..old.len() - 2000].to_vec(),
+        old[2000..].to_vec(),
+        [&generate_data(44, 100)[..], old].concat(),
+        old.to_vec(),
+    ]
+}
+
+#[test]
+fn trimmed_diffs_patch_correctly() {
+    let old = generate_data(40, 200_000);
+    for new in edits(&old) {
+        raw_patch(&old, &new);
+        assert_eq!(
+            bspatch43_vec(&old, &bsdiff43_vec(&old, &new).unwrap()[..]).unwrap(),
+            new
+        );
+
+        let patch = jbsdiff40_vec(&old, &new).unwrap();
+        let mut generated = Vec::new();
+        jbspatch40(&old, &mut generated, &patch[..]).unwrap();
+        assert_eq!(generated, new);
+
+        let mut patch = Vec::new();
+        bsdiff_raw_segmented(&old, &new, &mut patch, 4).unwrap();
+        let mut generated = vec![0; new.len()];
+        bspatch_raw_with(&old, &mut generated, &patch[..], &PatchOptions::strict()).unwrap();
+        assert_eq!(generated, new);
+    }
+}
+
+#[test]
+fn trimmed_diffs_are_small() {
+    let old = generate_data(40, 200_000);
+    let mut edited = old.clone();
+    edited[70000..70040].copy_from_slice(&generate_data(42, 40));
+    // The two ends and a few entries for the edit
+    assert!(raw_patch(&old, &edited).len() <= edited.len() + 4 * 24);
+    assert!(bsdiff43_vec(&old, &edited).unwrap().len() < 300);
+}
+
+#[test]
+fn appended_data_matches_recent_old_data() {
+    let old = generate_data(40, 200_000);
+    // A new record repeating the last one, as in a log
+    let new = [&old[..], &old[old.len() - 1000..]].concat();
+    raw_patch(&old, &new);
+    // Copied from old rather than stored as 1000 extra bytes
+    assert!(bsdiff43_vec(&old, &new).unwrap().len() < 300);
+}
+
+#[test]
+fn short_old_data_is_not_trimmed() {
+    let old = generate_data(40, 20_000);
+    let mut new = old.clone();
+    new[7000..7010].copy_from_slice(&generate_data(45, 10));
+    let mut indexed = Vec::new();
+    bsdiff_raw(&OldIndex::new(&old), &new, &mut indexed).unwrap();
+    assert!(raw_patch(&old, &new) == indexed);
+}
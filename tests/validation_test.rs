@@ -0,0 +1,213 @@
+use bsdiff_rs::{
+    bsdiff43_vec, bspatch40_vec, bspatch43_vec, bspatch43_with, bspatch_raw, bspatch_raw_with,
+    jbsdiff40_vec, jbspatch40_vec, jbspatch40_with, BsDiffError, PatchLimits, PatchOptions,
+};
+
+const OLD: &[u8] = b"0123456789";
+
+/// diff length, extra length, seek, diff bytes, extra bytes
+type Entry<'a> = (i64, i64, i64, &'a [u8], &'a [u8]);
+
+fn raw_patch(entries: &[Entry]) -> Vec<u8> {
+    let mut patch = Vec::new();
+    for (diff_len, extra_len, seek, diff, extra) in entries {
+        patch.extend_from_slice(&diff_len.to_le_bytes());
+        patch.extend_from_slice(&extra_len.to_le_bytes());
+        patch.extend_from_slice(&seek.to_le_bytes());
+        patch.extend_from_slice(diff);
+        patch.extend_from_slice(extra);
+    }
+    patch
+}
+
+fn expect_invalid_control(result: Result<(), BsDiffError>) {
+    match result {
+        Err(BsDiffError::InvalidControl) => {}
+        other => panic!("Expected InvalidControl, got {:?}", other),
+    }
+}
+
+#[test]
+fn negative_diff_length() {
+    let patch = raw_patch(&[(-1, 4, 0, &[], &[0; 4])]);
+    let mut new = [0u8; 4];
+    expect_invalid_control(bspatch_raw(OLD, &mut new, &patch[..]));
+}
+
+#[test]
+fn negative_extra_length() {
+    let patch = raw_patch(&[(0, -4, 0, &[], &[])]);
+    let mut new = [0u8; 4];
+    expect_invalid_control(bspatch_raw(OLD, &mut new, &patch[..]));
+}
+
+#[test]
+fn overflowing_seek() {
+    let patch = raw_patch(&[
+        (1, 0, i64::MAX, &[0], &[]),
+        (1, 0, 0, &[0], &[]),
+    ]);
+    let mut new = [0u8; 2];
+    expect_invalid_control(bspatch_raw(OLD, &mut new, &patch[..]));
+}
+
+#[test]
+fn out_of_range_read() {
+    let patch = raw_patch(&[(4, 0, 0, &[1; 4], &[]), (4, 0, 0, &[1; 4], &[])]);
+    let patch = [&raw_patch(&[(0, 0, 8, &[], &[])])[..], &patch[..]].concat();
+
+    let mut new = [0u8; 8];
+    bspatch_raw(OLD, &mut new, &patch[..]).expect("Lenient mode should pad with zeros");
+    assert_eq!(&new, b"9:\x01\x01\x01\x01\x01\x01");
+
+    let mut new = [0u8; 8];
+    expect_invalid_control(bspatch_raw_with(OLD, &mut new, &patch[..], &PatchOptions::strict()));
+}
+
+#[test]
+fn negative_seek() {
+    let patch = raw_patch(&[(0, 0, -1, &[], &[]), (2, 0, 0, &[1; 2], &[])]);
+
+    let mut new = [0u8; 2];
+    bspatch_raw(OLD, &mut new, &patch[..]).expect("Lenient mode should pad with zeros");
+    assert_eq!(&new, b"\x011");
+
+    let mut new = [0u8; 2];
+    expect_invalid_control(bspatch_raw_with(OLD, &mut new, &patch[..], &PatchOptions::strict()));
+}
+
+fn limited(limits: PatchLimits) -> PatchOptions {
+    PatchOptions {
+        limits,
+        ..PatchOptions::default()
+    }
+}
+
+fn expect_limit_exceeded<T: std::fmt::Debug>(result: Result<T, BsDiffError>) {
+    match result {
+        Err(BsDiffError::LimitExceeded { .. }) => {}
+        other => panic!("Expected LimitExceeded, got {:?}", other),
+    }
+}
+
+#[test]
+fn huge_declared_size() {
+    let mut patch = bsdiff43_vec(OLD, b"0123").unwrap();
+    patch[16..24].copy_from_slice(&u64::MAX.to_le_bytes());
+    let options = limited(PatchLimits {
+        max_new_size: 1 << 20,
+        ..PatchLimits::default()
+    });
+    expect_limit_exceeded(bspatch43_with(OLD, Vec::new(), &patch[..], &options));
+}
+
+#[test]
+fn huge_op_length() {
+    let patch = raw_patch(&[(1 << 40, 0, 0, &[], &[])]);
+    let mut new = [0u8; 4];
+    let options = limited(PatchLimits {
+        max_op_len: 1 << 20,
+        ..PatchLimits::default()
+    });
+    expect_limit_exceeded(bspatch_raw_with(OLD, &mut new, &patch[..], &options));
+}
+
+#[test]
+fn huge_sections() {
+    let new: Vec<u8> = (0..10_000u32).map(|i| (i * 7 % 251) as u8).collect();
+    let patch = jbsdiff40_vec(OLD, &new).unwrap();
+    let options = limited(PatchLimits {
+        max_memory: 64,
+        ..PatchLimits::default()
+    });
+    expect_limit_exceeded(jbspatch40_with(OLD, Vec::new(), &patch[..], &options));
+
+    let options = limited(PatchLimits {
+        max_memory: (patch.len() + new.len()) as u64,
+        ..PatchLimits::default()
+    });
+    let mut generated = Vec::new();
+    jbspatch40_with(OLD, &mut generated, &patch[..], &options).unwrap();
+    assert_eq!(generated, new);
+}
+
+#[test]
+fn memory_limit_covers_everything_held_at_once() {
+    // The sections of the patch and the scratch buffer, as large as the output here, fit on
+    // their own but not together
+    let new: Vec<u8> = (0..10_000u32).map(|i| (i * 7 % 251) as u8).collect();
+    let patch = jbsdiff40_vec(OLD, &new).unwrap();
+    let total = (patch.len() - 32 + new.len()) as u64;
+    let options = limited(PatchLimits {
+        max_memory: total - 1,
+        ..PatchLimits::default()
+    });
+    expect_limit_exceeded(jbspatch40_with(OLD, Vec::new(), &patch[..], &options));
+
+    let options = limited(PatchLimits {
+        max_memory: total,
+        ..PatchLimits::default()
+    });
+    let mut generated = Vec::new();
+    jbspatch40_with(OLD, &mut generated, &patch[..], &options).unwrap();
+    assert_eq!(generated, new);
+}
+
+#[test]
+fn huge_section_lengths_without_limits() {
+    let patch = jbsdiff40_vec(OLD, b"0123").unwrap();
+    for &len in &[u64::MAX, 1 << 40] {
+        for &at in &[8, 16] {
+            let mut damaged = patch.clone();
+            damaged[at..at + 8].copy_from_slice(&len.to_le_bytes());
+            match jbspatch40_vec(OLD, &damaged[..]) {
+                Err(BsDiffError::CorruptStream(_)) => {}
+                other => panic!("Expected CorruptStream, got {:?}", other),
+            }
+            // Lengths with the sign bit set are rejected outright by the sign-magnitude format
+            assert!(bspatch40_vec(OLD, &damaged[..]).is_err());
+        }
+    }
+}
+
+#[test]
+fn huge_declared_size_without_limits() {
+    let mut patch = bsdiff43_vec(OLD, b"0123").unwrap();
+    for &len in &[u64::MAX, 1 << 62] {
+        patch[16..24].copy_from_slice(&len.to_le_bytes());
+        assert!(bspatch43_vec(OLD, &patch[..]).is_err());
+    }
+}
+
+#[cfg(feature = "c_backend")]
+#[test]
+fn huge_declared_size_in_c_backend() {
+    use bsdiff_rs::{c::CBackend, BsDiff};
+
+    // The C backend patches into a buffer of the declared size, allocated before any sections
+    for &len in &[u64::MAX, 1 << 62] {
+        let patch = [&b"ENDSLEY/BSDIFF43"[..], &len.to_le_bytes()].concat();
+        match BsDiff::<CBackend>::bspatch43_vec(OLD, &patch[..]) {
+            Err(BsDiffError::Io(_)) => {}
+            other => panic!("Expected Io, got {:?}", other.map(|new| new.len())),
+        }
+    }
+}
+
+#[test]
+fn long_runs_use_bounded_memory() {
+    let old: Vec<u8> = (0..300_000u32).map(|i| (i * 31 % 253) as u8).collect();
+    let mut new = old.clone();
+    new[1000] ^= 0xff;
+    new[200_000] ^= 0xff;
+    new.extend((0..100_000u32).map(|i| (i * 17 % 241) as u8));
+
+    let patch = bsdiff43_vec(&old, &new).unwrap();
+    let options = limited(PatchLimits {
+        max_memory: 64 * 1024,
+        ..PatchLimits::default()
+    });
+    let mut generated = Vec::new();
+    bspatch43_with(&old, &mut generated, &patch[..], &options).unwrap();
+    assert_eq!(generated, new);
+}